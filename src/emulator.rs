@@ -0,0 +1,223 @@
+use crate::hardware::color::ColorZones;
+use crate::hardware::cpu::CPU;
+use crate::hardware::font::Font;
+use crate::hardware::memory::MemoryLayout;
+use crate::hardware::quirks::Quirks;
+use crate::hardware::rom::InstructionSet;
+use crate::hardware::timing::{Timing, VIP_CYCLES_PER_FRAME};
+use crate::hardware::vip::{Vip, VipRoms};
+use crate::hardware::{CHIP8_HEIGHT, CHIP8_TIMER_RATE, CHIP8_WIDTH};
+
+// at least one instruction runs each frame
+const MIN_CYCLES_PER_FRAME: f64 = 1.;
+
+// Runs the CPU in 60Hz frames, ticking the timers once per frame.
+pub struct Emulator {
+    cpu: CPU,
+    // when set, the whole COSMAC VIP runs the game instead of the CPU
+    vip: Option<Box<Vip>>,
+    quirks: Quirks,
+    layout: MemoryLayout,
+    font: Font,
+    extension: InstructionSet,
+    // kept to restart the game on a reset
+    rom: Vec<u8>,
+    timing: Timing,
+    // instructions per frame, or machine cycles with the VIP timing
+    cycles_per_frame: f64,
+    pending_cycles: f64,
+    pub frame_count: u64,
+}
+
+pub struct FrameState {
+    pub updated_vram: bool,
+    pub beep: bool,
+    // instructions run during the frame
+    pub cycles: u32,
+    // whether the frame ended in FX0A waiting for a key
+    pub waiting_for_key: bool,
+}
+
+impl Emulator {
+    // `speed` is the number of instructions per second, rounded to whole
+    // instructions a frame so that the speed hotkeys can step back to it,
+    // and at least one a frame.
+    pub fn new(quirks: Quirks, layout: MemoryLayout, font: Font, rom: Vec<u8>, speed: f64) -> Self {
+        let mut cpu = CPU::new(quirks, layout, &font);
+        cpu.load_memory(&rom);
+
+        Emulator {
+            cpu,
+            vip: None,
+            quirks,
+            layout,
+            font,
+            extension: InstructionSet::Chip8,
+            rom,
+            timing: Timing::Uniform,
+            cycles_per_frame: (speed / CHIP8_TIMER_RATE).round().max(MIN_CYCLES_PER_FRAME),
+            pending_cycles: 0.,
+            frame_count: 0,
+        }
+    }
+
+    // With the VIP timing each frame runs as many instructions as the VIP
+    // would in 1/60 s, instead of the number set by the speed.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.pending_cycles = 0.;
+    }
+
+    // Decodes the extra instructions of CHIP-8E or CHIP-8X.
+    pub fn set_extension(&mut self, extension: InstructionSet) {
+        self.extension = extension;
+        self.cpu.extension = extension;
+    }
+
+    // Runs the game on the original interpreter of an emulated COSMAC VIP,
+    // which sets its own speed.
+    pub fn use_vip(&mut self, roms: &VipRoms) {
+        self.vip = Some(Box::new(Vip::new(roms, &self.rom)));
+        self.frame_count = 0;
+    }
+
+    // Restarts the game from a fresh CPU, as if the machine was switched
+    // off and on again.
+    pub fn reset(&mut self) {
+        self.cpu = CPU::new(self.quirks, self.layout, &self.font);
+        self.cpu.extension = self.extension;
+        self.cpu.load_memory(&self.rom);
+        if let Some(vip) = &mut self.vip {
+            vip.reset(&self.rom);
+        }
+        self.pending_cycles = 0.;
+        self.frame_count = 0;
+    }
+
+    // Replaces the game and restarts.
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+        self.reset();
+    }
+
+    pub fn cycles_per_frame(&self) -> f64 {
+        self.cycles_per_frame
+    }
+
+    pub fn vram(&self) -> &[u8; CHIP8_WIDTH * CHIP8_HEIGHT] {
+        match &self.vip {
+            Some(vip) => &vip.vram,
+            None => &self.cpu.vram,
+        }
+    }
+
+    // The colours of the screen, only CHIP-8X has them.
+    pub fn color_zones(&self) -> Option<&ColorZones> {
+        match &self.vip {
+            None if self.extension == InstructionSet::Chip8X => Some(&self.cpu.colors),
+            _ => None,
+        }
+    }
+
+    // The VIP and its timing have their own speed, which can't be changed.
+    pub fn has_fixed_speed(&self) -> bool {
+        self.vip.is_some() || self.timing == Timing::Vip
+    }
+
+    // Has no effect with a fixed speed.
+    pub fn set_cycles_per_frame(&mut self, cycles: f64) {
+        if self.has_fixed_speed() {
+            return;
+        }
+        self.cycles_per_frame = cycles.max(MIN_CYCLES_PER_FRAME);
+    }
+
+    pub fn run_frame(&mut self, keypad: [bool; 16]) -> FrameState {
+        if let Some(vip) = &mut self.vip {
+            let frame = vip.run_frame(keypad);
+            self.frame_count += 1;
+            return FrameState {
+                updated_vram: frame.updated_vram,
+                beep: frame.beep,
+                cycles: frame.instructions,
+                // the interpreter in the VIP memory doesn't tell
+                waiting_for_key: false,
+            };
+        }
+
+        let mut updated_vram = false;
+        let mut cycles = 0;
+        let mut waiting_for_key = false;
+        self.pending_cycles += match self.timing {
+            Timing::Uniform => self.cycles_per_frame,
+            Timing::Vip => VIP_CYCLES_PER_FRAME as f64,
+        };
+        // a slow instruction may run into the next frame
+        while self.pending_cycles >= 1. {
+            cycles += 1;
+            let state = self.cpu.emulate_cycle(keypad);
+            self.pending_cycles -= match self.timing {
+                Timing::Uniform => 1.,
+                Timing::Vip => state.vip_cycles as f64,
+            };
+            updated_vram |= state.updated_vram;
+            waiting_for_key = state.waiting_for_key;
+
+            // nothing changes until the next frame
+            if state.waiting_for_key || state.waiting_for_vblank {
+                self.pending_cycles = 0.;
+                break;
+            }
+        }
+
+        self.cpu.vblank();
+        let beep = self.cpu.tick_timers();
+        self.frame_count += 1;
+
+        FrameState {
+            updated_vram,
+            beep,
+            cycles,
+            waiting_for_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::font::FontSet;
+    use crate::hardware::quirks::Platform;
+
+    fn emulator(speed: f64) -> Emulator {
+        Emulator::new(
+            Platform::Chip8.quirks(),
+            MemoryLayout::default(),
+            FontSet::Vip.font(),
+            vec![0x12, 0x00],
+            speed,
+        )
+    }
+
+    #[test]
+    fn speed_is_whole_instructions_a_frame() {
+        let mut emulator = emulator(500.);
+        assert_eq!(emulator.cycles_per_frame(), 8.);
+
+        let initial = emulator.cycles_per_frame();
+        emulator.set_cycles_per_frame(emulator.cycles_per_frame() + 1.);
+        emulator.set_cycles_per_frame(emulator.cycles_per_frame() - 1.);
+        assert_eq!(emulator.cycles_per_frame(), initial);
+
+        assert_eq!(emulator.run_frame([false; 16]).cycles, 8);
+    }
+
+    #[test]
+    fn speed_is_at_least_one_instruction_a_frame() {
+        let mut emulator = emulator(10.);
+        assert_eq!(emulator.cycles_per_frame(), 1.);
+
+        emulator.set_cycles_per_frame(0.);
+        assert_eq!(emulator.cycles_per_frame(), 1.);
+    }
+}
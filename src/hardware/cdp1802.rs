@@ -0,0 +1,468 @@
+// What the processor is wired to: the memory, the I/O ports and the
+// external flags.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+
+    // OUT 1-7, with the byte read from memory.
+    fn output(&mut self, _port: usize, _value: u8) {}
+
+    // INP 1-7, returns the byte stored to memory and D.
+    fn input(&mut self, _port: usize) -> u8 {
+        0
+    }
+
+    // Whether EF1 to EF4, numbered from 0, is active.
+    fn flag(&self, _number: usize) -> bool {
+        false
+    }
+}
+
+// Plain memory with nothing on the ports, addresses wrap around at its end.
+impl Bus for [u8] {
+    fn read(&mut self, address: u16) -> u8 {
+        self[address as usize % self.len()]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let length = self.len();
+        self[address as usize % length] = value;
+    }
+}
+
+// An RCA CDP1802, the processor of the COSMAC VIP. It runs the machine
+// code subroutines CHIP-8 programs call with 0NNN, or the whole VIP.
+#[derive(Clone, Debug)]
+pub struct Cdp1802 {
+    // 16 bit scratchpad registers, any of them can be the program counter
+    pub r: [u16; 16],
+    // accumulator
+    pub d: u8,
+    // carry flag
+    pub df: bool,
+    // which register is the program counter
+    pub p: usize,
+    // which register is the data pointer
+    pub x: usize,
+    // X and P saved by MARK and interrupts
+    pub t: u8,
+    pub ie: bool,
+    // the output flip-flop, which drives the VIP speaker
+    pub q: bool,
+    // stopped by IDL until an interrupt or DMA
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    // The state after a reset, running from address 0 with R0.
+    fn default() -> Self {
+        Cdp1802 {
+            r: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+}
+
+impl Cdp1802 {
+    // Runs one instruction and returns the machine cycles it took, each
+    // of them is 8 clock cycles.
+    pub fn step<B: Bus + ?Sized>(&mut self, bus: &mut B) -> u64 {
+        if self.idle {
+            return 1;
+        }
+        let op_code = self.fetch(bus);
+        let n = (op_code & 0xF) as usize;
+        match op_code >> 4 {
+            // IDL
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n]),
+            // INC, DEC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => self.short_branch(bus, n),
+            // LDA
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // STR
+            0x5 => bus.write(self.r[n], self.d),
+            0x6 => self.input_output(bus, n),
+            0x7 => self.control(bus, n),
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            // SEP, SEX
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => self.arithmetic(bus, n),
+        }
+        2
+    }
+
+    // Answers an interrupt request if interrupts are enabled, saving X
+    // and P in T and running the handler with R1. Takes a machine cycle.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+        self.t = (self.x << 4 | self.p) as u8;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    // A DMA output cycle, reading the byte R0 points at for the device.
+    pub fn dma_out<B: Bus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    fn fetch<B: Bus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[self.p]);
+        self.r[self.p] = self.r[self.p].wrapping_add(1);
+        value
+    }
+
+    // The condition tested by the branches and skips 3N and CN, each one
+    // comes with its opposite 8 instructions later.
+    fn condition<B: Bus + ?Sized>(&self, bus: &B, n: usize) -> bool {
+        let condition = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag - 4),
+        };
+        condition != (n >= 8)
+    }
+
+    // BR, BQ, BZ, BDF, B1-B4 and their opposites, SKP for 38. The target
+    // is in the same page as the address byte.
+    fn short_branch<B: Bus + ?Sized>(&mut self, bus: &mut B, n: usize) {
+        let pc = self.r[self.p];
+        if self.condition(bus, n) {
+            self.r[self.p] = pc & 0xFF00 | bus.read(pc) as u16;
+        } else {
+            self.r[self.p] = pc.wrapping_add(1);
+        }
+    }
+
+    // LBR, LBQ, LBZ, LBDF and their opposites, or for C4-C7 and CC-CF the
+    // NOP and long skips, whose conditions are mixed up a bit.
+    fn long_branch<B: Bus + ?Sized>(&mut self, bus: &mut B, n: usize) {
+        let pc = self.r[self.p];
+        match n {
+            0x4 => {}
+            0x5 => self.long_skip(!self.q),
+            0x6 => self.long_skip(self.d != 0),
+            0x7 => self.long_skip(!self.df),
+            0x8 => self.long_skip(true),
+            0xC => self.long_skip(self.ie),
+            0xD => self.long_skip(self.q),
+            0xE => self.long_skip(self.d == 0),
+            0xF => self.long_skip(self.df),
+            _ if self.condition(bus, n) => {
+                let high = bus.read(pc) as u16;
+                let low = bus.read(pc.wrapping_add(1)) as u16;
+                self.r[self.p] = high << 8 | low;
+            }
+            _ => self.r[self.p] = pc.wrapping_add(2),
+        }
+    }
+
+    fn long_skip(&mut self, skip: bool) {
+        if skip {
+            self.r[self.p] = self.r[self.p].wrapping_add(2);
+        }
+    }
+
+    // IRX, OUT 1-7 and INP 1-7. 68 is only an instruction on the 1804.
+    fn input_output<B: Bus + ?Sized>(&mut self, bus: &mut B, n: usize) {
+        match n {
+            0x0 => self.r[self.x] = self.r[self.x].wrapping_add(1),
+            0x1..=0x7 => {
+                let value = bus.read(self.r[self.x]);
+                bus.output(n, value);
+                self.r[self.x] = self.r[self.x].wrapping_add(1);
+            }
+            0x8 => {}
+            _ => {
+                let value = bus.input(n - 8);
+                bus.write(self.r[self.x], value);
+                self.d = value;
+            }
+        }
+    }
+
+    fn control<B: Bus + ?Sized>(&mut self, bus: &mut B, n: usize) {
+        let rx = self.r[self.x];
+        match n {
+            // RET, DIS
+            0x0 | 0x1 => {
+                let value = bus.read(rx);
+                self.r[self.x] = rx.wrapping_add(1);
+                self.x = (value >> 4) as usize;
+                self.p = (value & 0xF) as usize;
+                self.ie = n == 0x0;
+            }
+            // LDXA, STXD
+            0x2 => {
+                self.d = bus.read(rx);
+                self.r[self.x] = rx.wrapping_add(1);
+            }
+            0x3 => {
+                bus.write(rx, self.d);
+                self.r[self.x] = rx.wrapping_sub(1);
+            }
+            // ADC, SDB, SMB
+            0x4 => {
+                let value = bus.read(rx);
+                self.add(value, true);
+            }
+            0x5 => {
+                let value = bus.read(rx);
+                self.subtract(value, self.d, true);
+            }
+            0x7 => {
+                let value = bus.read(rx);
+                self.subtract(self.d, value, true);
+            }
+            // SHRC
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            // SAV, MARK
+            0x8 => bus.write(rx, self.t),
+            0x9 => {
+                self.t = (self.x << 4 | self.p) as u8;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // ADCI, SDBI, SMBI
+            0xC => {
+                let value = self.fetch(bus);
+                self.add(value, true);
+            }
+            0xD => {
+                let value = self.fetch(bus);
+                self.subtract(value, self.d, true);
+            }
+            0xF => {
+                let value = self.fetch(bus);
+                self.subtract(self.d, value, true);
+            }
+            // SHLC
+            _ => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+        }
+    }
+
+    // FN, the logic and arithmetic on M(R(X)), or for F8-FF on the byte
+    // after the instruction.
+    fn arithmetic<B: Bus + ?Sized>(&mut self, bus: &mut B, n: usize) {
+        let value = match n {
+            0x0..=0x5 | 0x7 => bus.read(self.r[self.x]),
+            0x6 | 0xE => 0,
+            _ => self.fetch(bus),
+        };
+        match n & 0x7 {
+            // LDX, LDI
+            0x0 => self.d = value,
+            // OR, AND, XOR and their immediate versions
+            0x1 => self.d |= value,
+            0x2 => self.d &= value,
+            0x3 => self.d ^= value,
+            // ADD, SD, SM
+            0x4 => self.add(value, false),
+            0x5 => self.subtract(value, self.d, false),
+            0x7 => self.subtract(self.d, value, false),
+            // SHR
+            _ if n == 0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            // SHL
+            _ => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+        }
+    }
+
+    fn add(&mut self, value: u8, with_carry: bool) {
+        let carry = (with_carry && self.df) as u16;
+        let result = self.d as u16 + value as u16 + carry;
+        self.d = result as u8;
+        self.df = result > 0xFF;
+    }
+
+    // DF is set when there is no borrow.
+    fn subtract(&mut self, a: u8, b: u8, with_borrow: bool) {
+        let borrow = (with_borrow && !self.df) as i16;
+        let result = a as i16 - b as i16 - borrow;
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a program from address 0 with R0 as the program counter, for
+    // the given number of instructions.
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, Vec<u8>) {
+        let mut memory = vec![0; 256];
+        memory[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::default();
+        for _ in 0..steps {
+            cpu.step(&mut memory[..]);
+        }
+        (cpu, memory)
+    }
+
+    #[test]
+    fn long_skips() {
+        // after a reset D is 0, DF and Q are off and interrupts are enabled
+        let cases = [
+            (0xC5, true),  // LSNQ
+            (0xC6, false), // LSNZ
+            (0xC7, true),  // LSNF
+            (0xC8, true),  // LSKP
+            (0xCC, true),  // LSIE
+            (0xCD, false), // LSQ
+            (0xCE, true),  // LSZ
+            (0xCF, false), // LSDF
+        ];
+        for &(op_code, skipped) in &cases {
+            let (cpu, _) = run(&[op_code, 0xF8, 0x01, 0xF8, 0x02], 2);
+            assert_eq!(cpu.d, if skipped { 2 } else { 1 }, "{:02X}", op_code);
+            assert_eq!(cpu.r[0], if skipped { 5 } else { 3 }, "{:02X}", op_code);
+        }
+    }
+
+    #[test]
+    fn long_skip_conditions_follow_d_and_df() {
+        // LDI 5, LSNZ skips over LDI 1
+        let (cpu, _) = run(&[0xF8, 0x05, 0xC6, 0xF8, 0x01, 0xF8, 0x02], 3);
+        assert_eq!(cpu.d, 2);
+        // LDI FF, ADI 1 sets DF, LSDF skips over LDI 1
+        let (cpu, _) = run(&[0xF8, 0xFF, 0xFC, 0x01, 0xCF, 0xF8, 0x01, 0xF8, 0x02], 4);
+        assert!(cpu.df);
+        assert_eq!(cpu.d, 2);
+    }
+
+    #[test]
+    fn nop_takes_three_cycles() {
+        let mut memory = [0xC4, 0x00, 0x00];
+        let mut cpu = Cdp1802::default();
+        assert_eq!(cpu.step(&mut memory[..]), 3);
+        assert_eq!(cpu.r[0], 1);
+    }
+
+    #[test]
+    fn subtract_sets_df_without_borrow() {
+        // SDI: D = immediate - D
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFD, 0x03], 2);
+        assert_eq!((cpu.d, cpu.df), (0xFE, false));
+        let (cpu, _) = run(&[0xF8, 0x03, 0xFD, 0x05], 2);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+        // SMI: D = D - immediate
+        let (cpu, _) = run(&[0xF8, 0x03, 0xFF, 0x05], 2);
+        assert_eq!((cpu.d, cpu.df), (0xFE, false));
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFF, 0x03], 2);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+    }
+
+    #[test]
+    fn subtract_with_borrow_uses_df() {
+        // SMI 1 leaves DF set, so SMBI 1 doesn't borrow
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFF, 0x01, 0x7F, 0x01], 3);
+        assert_eq!((cpu.d, cpu.df), (0x03, true));
+        // SMI 6 borrows, so SMBI 1 takes one more
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFF, 0x06, 0x7F, 0x01], 3);
+        assert_eq!((cpu.d, cpu.df), (0xFD, true));
+        // SDBI: D = immediate - D - borrow, 3 - FF - 1 borrows again
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFF, 0x06, 0x7D, 0x03], 3);
+        assert_eq!((cpu.d, cpu.df), (0x03, false));
+    }
+
+    #[test]
+    fn shifts_through_df() {
+        // SMI 0 sets DF, SHRC shifts it in at the top and bit 0 out
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFF, 0x00, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0xC0, true));
+        // SHLC shifts DF in at the bottom and bit 7 out
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFF, 0x01, 0x7E], 3);
+        assert_eq!((cpu.d, cpu.df), (0x01, true));
+        // SHR and SHL don't shift DF in
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFF, 0x00, 0xF6], 3);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        let (cpu, _) = run(&[0xF8, 0x41, 0xFE], 2);
+        assert_eq!((cpu.d, cpu.df), (0x82, false));
+    }
+
+    #[test]
+    fn mark_and_return() {
+        let program = [
+            0xF8, 0x80, // LDI 80
+            0xA2, // PLO R2, the stack at 80
+            0xF8, 0x10, // LDI 10
+            0xA3, // PLO R3
+            0xD3, // SEP R3, run from 10
+        ];
+        let mut memory = program.to_vec();
+        memory.resize(0x10, 0);
+        memory.extend_from_slice(&[
+            0xE5, // SEX R5
+            0x79, // MARK, saves X=5 and P=3
+            0xE2, // SEX R2
+            0x60, // IRX
+            0x70, // RET
+        ]);
+        let (cpu, memory) = run(&memory, 10);
+        assert_eq!(memory[0x80], 0x53);
+        assert_eq!(cpu.t, 0x53);
+        assert_eq!(cpu.r[2], 0x81);
+        assert_eq!((cpu.x, cpu.p), (5, 3));
+        assert_eq!(cpu.r[3], 0x15);
+        assert!(cpu.ie);
+    }
+
+    #[test]
+    fn disable_loads_x_and_p() {
+        // DIS with X=0 reads the byte after it
+        let (cpu, _) = run(&[0x71, 0x23], 1);
+        assert_eq!((cpu.x, cpu.p), (2, 3));
+        assert_eq!(cpu.r[0], 2);
+        assert!(!cpu.ie);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+// CHIP-8X draws in colour with the VP-590 colour board of the VIP, which
+// gives each zone of 8x4 pixels its own foreground colour. The pixels that
+// are off show the background colour of the whole screen.
+pub const ZONE_WIDTH: usize = 8;
+pub const ZONE_HEIGHT: usize = 4;
+pub const ZONE_COLUMNS: usize = CHIP8_WIDTH / ZONE_WIDTH;
+pub const ZONE_ROWS: usize = CHIP8_HEIGHT / ZONE_HEIGHT;
+
+// The foreground colours as 0xRRGGBB, picked by the 3 colour bits:
+// black, red, blue, magenta, green, yellow, cyan and white.
+pub const FOREGROUND_COLORS: [u32; 8] = [
+    0x000000, 0xFF0000, 0x0000FF, 0xFF00FF, 0x00FF00, 0xFFFF00, 0x00FFFF, 0xFFFFFF,
+];
+// the background colours 02A0 steps through: blue, black, green and red
+pub const BACKGROUND_COLORS: [u32; 4] = [0x000080, 0x000000, 0x008000, 0x800000];
+// the zones are red until a program colours them
+const DEFAULT_FOREGROUND: u8 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ColorZones {
+    // index in the background colours
+    background: usize,
+    // the foreground colour of each zone, row by row
+    zones: [u8; ZONE_COLUMNS * ZONE_ROWS],
+}
+
+impl Default for ColorZones {
+    fn default() -> Self {
+        ColorZones {
+            background: 0,
+            zones: [DEFAULT_FOREGROUND; ZONE_COLUMNS * ZONE_ROWS],
+        }
+    }
+}
+
+impl ColorZones {
+    // Index of the background colour in BACKGROUND_COLORS.
+    pub fn background(&self) -> usize {
+        self.background
+    }
+
+    // Index in FOREGROUND_COLORS of the colour of a pixel that is on.
+    pub fn foreground(&self, x: usize, y: usize) -> usize {
+        let zone = (y / ZONE_HEIGHT % ZONE_ROWS) * ZONE_COLUMNS + x / ZONE_WIDTH % ZONE_COLUMNS;
+        self.zones[zone] as usize
+    }
+
+    pub fn background_color(&self) -> u32 {
+        BACKGROUND_COLORS[self.background()]
+    }
+
+    pub fn foreground_color(&self, x: usize, y: usize) -> u32 {
+        FOREGROUND_COLORS[self.foreground(x, y)]
+    }
+
+    pub fn next_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUND_COLORS.len();
+    }
+
+    // Colours a block of zones, which wraps around the screen edges.
+    pub fn fill(&mut self, column: usize, row: usize, columns: usize, rows: usize, color: u8) {
+        for row in row..row + rows {
+            for column in column..column + columns {
+                let zone = (row % ZONE_ROWS) * ZONE_COLUMNS + column % ZONE_COLUMNS;
+                self.zones[zone] = color & 0x7;
+            }
+        }
+    }
+}
@@ -1,31 +1,57 @@
-use super::font::CHIP8_FONTSET;
+use super::cdp1802::{Bus, Cdp1802};
+use super::color::ColorZones;
+use super::font::Font;
 use super::instruction::Instruction;
+use super::memory::MemoryLayout;
+use super::quirks::Quirks;
+use super::rom::InstructionSet;
+use super::timing;
 use std::fmt::{Debug, Formatter, Result};
-use std::time::Instant;
 
-const CHIP8_TIMER_DELAY: u128 = ((1. / 60. * 1000.) + 0.) as u128;
-const CHIP8_RAM_SIZE: usize = 4096;
 const CHIP8_VRAM_SIZE: usize = 64 * 32;
-pub const CHIP8_START_POINT: usize = 0x200;
 
+// Where the COSMAC VIP interpreter keeps its state, counted back from the
+// end of the RAM, for the machine code subroutines that use it.
+const VIP_STACK_TOP: usize = 0x131;
+const VIP_REGISTERS: usize = 0x110;
+const VIP_DISPLAY: usize = 0x100;
+// machine cycles a subroutine may run for, about 5 seconds on a VIP
+const MAX_MACHINE_CODE_CYCLES: u64 = 1_000_000;
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub(crate) registers: [u8; 16], // last register contains carry flag
     pub(crate) i: usize,            //memory index
     pub(crate) pc: usize,
     pub(crate) sp: usize,
 
-    pub(crate) ram: [u8; CHIP8_RAM_SIZE],
+    pub(crate) ram: Vec<u8>,
     pub vram: [u8; CHIP8_VRAM_SIZE],
     pub vram_flag: bool,
-    pub(crate) stack: [usize; 16],
+    // the colours of CHIP-8X
+    pub colors: ColorZones,
+    pub(crate) stack: Vec<usize>,
 
     pub(crate) keypad: [bool; 16],
+    previous_keypad: [bool; 16],
     pub(crate) keypad_dst: usize,
+    pub(crate) awaited_key: Option<usize>,
 
     pub(crate) delay_timer: u8,
     pub(crate) sound_timer: u8,
     pub(crate) await_keypad: bool,
-    timer_delay: Instant,
+    pub(crate) await_vblank: bool,
+    // CHIP-8E waits for the delay timer to run out
+    pub(crate) await_delay: bool,
+    pub(crate) quirks: Quirks,
+    // the extra instructions decoded, CHIP-8 for none
+    pub(crate) extension: InstructionSet,
+    pub(crate) layout: MemoryLayout,
+    pub(crate) cdp1802: Cdp1802,
+    // the key selected by OUT 2 in machine code
+    key_latch: usize,
+    // machine cycles the 1802 ran for in the last 0NNN call
+    machine_code_cycles: u64,
 }
 
 impl Debug for CPU {
@@ -37,93 +63,416 @@ impl Debug for CPU {
             .field("delay_timer", &self.delay_timer)
             .field("sound_timer", &self.sound_timer)
             .field("await_keypad", &self.await_keypad)
+            .field("await_vblank", &self.await_vblank)
+            .field("await_delay", &self.await_delay)
             .finish()
     }
 }
 
 impl CPU {
-    pub fn new() -> Self {
+    pub fn new(quirks: Quirks, layout: MemoryLayout, font: &Font) -> Self {
         //load font-set
-        let mut ram = [0u8; CHIP8_RAM_SIZE];
-        for i in 0..CHIP8_FONTSET.len() {
-            ram[i] = CHIP8_FONTSET[i];
-        }
+        let mut ram = vec![0u8; layout.ram_size];
+        let small = layout.font_base..layout.font_base + font.small.len();
+        ram[small].copy_from_slice(&font.small);
+        let big = layout.big_font_base..layout.big_font_base + font.big.len();
+        ram[big].copy_from_slice(&font.big);
 
         CPU {
             registers: [0u8; 16],
             i: 0,
-            pc: CHIP8_START_POINT,
+            pc: layout.start,
             sp: 0,
             ram,
             vram: [0u8; CHIP8_VRAM_SIZE],
             vram_flag: false,
-            stack: [0; 16],
+            colors: ColorZones::default(),
+            stack: vec![0; layout.stack_depth],
             keypad: [false; 16],
+            previous_keypad: [false; 16],
             keypad_dst: 0,
+            awaited_key: None,
             delay_timer: 0,
             sound_timer: 0,
             await_keypad: false,
-            timer_delay: Instant::now(),
+            await_vblank: false,
+            await_delay: false,
+            quirks,
+            extension: InstructionSet::Chip8,
+            layout,
+            cdp1802: Cdp1802::default(),
+            key_latch: 0,
+            machine_code_cycles: 0,
         }
     }
 
+    // Copies the program into memory, whatever doesn't fit is left out.
     pub fn load_memory(&mut self, data: &[u8]) {
-        for i in 0..data.len() {
-            self.ram[i + CHIP8_START_POINT] = data[i];
-        }
+        let start = self.layout.start;
+        let length = data.len().min(self.layout.max_rom_size());
+        self.ram[start..start + length].copy_from_slice(&data[..length]);
+    }
+
+    // Memory accesses past the end of the RAM wrap around to the start,
+    // as the address lines of a smaller memory would.
+    pub(crate) fn read(&self, address: usize) -> u8 {
+        self.ram[address % self.ram.len()]
+    }
+
+    pub(crate) fn write(&mut self, address: usize, value: u8) {
+        let length = self.ram.len();
+        self.ram[address % length] = value;
     }
 
     pub fn emulate_cycle(&mut self, keypad: [bool; 16]) -> CpuState {
+        self.previous_keypad = self.keypad;
         self.keypad = keypad;
         self.vram_flag = false;
+
         if self.await_keypad {
-            for (i, &key) in keypad.iter().enumerate() {
-                if key {
-                    self.await_keypad = false;
-                    self.registers[self.keypad_dst] = i as u8;
-                    break;
-                }
-            }
+            self.wait_for_key();
         }
-        if self.await_keypad {
+        if self.await_delay && self.delay_timer == 0 {
+            self.await_delay = false;
+        }
+        if self.await_keypad || self.await_vblank || self.await_delay {
             return CpuState {
                 updated_vram: false,
-                beep: false,
+                waiting_for_key: self.await_keypad,
+                // the timer only changes once a frame
+                waiting_for_vblank: self.await_vblank || self.await_delay,
+                vip_cycles: 0,
             };
         }
         //fetch
         let op_code = self.fetch_instruction();
-        //println!("{:#04X}", op_code);
+        let next_pc = self.pc;
+        let vx = self.registers[(op_code >> 8 & 0xF) as usize];
+        self.machine_code_cycles = 0;
         //decode
-        let instruction = Instruction::decode(&op_code);
+        let instruction = Instruction::decode(&op_code, self.extension);
         //execute
+        instruction.execute(self);
 
-        if self.timer_delay.elapsed().as_millis() > CHIP8_TIMER_DELAY {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
-            }
+        // 5XY1 of CHIP-8E is among the 5XYN skips, 0188 always skips
+        let skip = matches!(op_code >> 12, 0x3 | 0x4 | 0x5 | 0x9 | 0xE)
+            || (self.extension == InstructionSet::Chip8E && op_code == 0x0188);
+        let skipped = self.pc == next_pc + 2 && skip;
+        CpuState {
+            updated_vram: self.vram_flag,
+            waiting_for_key: self.await_keypad,
+            waiting_for_vblank: self.await_vblank || self.await_delay,
+            vip_cycles: timing::vip_cycles(op_code, vx, skipped) + self.machine_code_cycles,
+        }
+    }
+
+    // Signals the start of a new frame to instructions waiting for it.
+    pub fn vblank(&mut self) {
+        self.await_vblank = false;
+    }
+
+    // Counts the delay and sound timers down, must be called at 60Hz
+    // regardless of how many instructions run in between.
+    // Returns whether the buzzer should be sounding.
+    pub fn tick_timers(&mut self) -> bool {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
 
-            if self.sound_timer > 0 {
-                self.sound_timer -= 1;
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+
+        self.sound_timer > 0
+    }
+
+    // FX0A only reacts to keys going down while it waits, so a key that
+    // is still held from before doesn't complete it straight away.
+    // With the key release quirk the pressed key must also go up again.
+    fn wait_for_key(&mut self) {
+        for key in 0..self.keypad.len() {
+            let pressed = self.keypad[key] && !self.previous_keypad[key];
+            let released = !self.keypad[key] && self.previous_keypad[key];
+
+            match self.awaited_key {
+                None if pressed && self.quirks.key_wait_release => {
+                    self.awaited_key = Some(key);
+                }
+                None if pressed => {
+                    self.complete_key_wait(key);
+                    return;
+                }
+                Some(awaited) if awaited == key && released => {
+                    self.complete_key_wait(key);
+                    return;
+                }
+                _ => {}
             }
-            self.timer_delay = Instant::now();
         }
-        instruction.execute(self);
+    }
 
-        return CpuState {
-            updated_vram: self.vram_flag,
-            beep: self.sound_timer > 0,
+    fn complete_key_wait(&mut self, key: usize) {
+        self.registers[self.keypad_dst] = key as u8;
+        self.awaited_key = None;
+        self.await_keypad = false;
+    }
+
+    // Runs the 1802 machine code at the address until it returns to the
+    // interpreter with SEP R4. The V registers, I, the timers and the
+    // screen are put where the VIP interpreter keeps them for the call.
+    pub(crate) fn call_machine_code(&mut self, address: usize) {
+        let end = self.ram.len();
+        let registers = end - VIP_REGISTERS;
+        let display = end - VIP_DISPLAY;
+        self.ram[registers..registers + 16].copy_from_slice(&self.registers);
+        self.store_display(display);
+
+        let cdp1802 = &mut self.cdp1802;
+        cdp1802.r[2] = (end - VIP_STACK_TOP) as u16;
+        cdp1802.x = 2;
+        cdp1802.r[3] = address as u16;
+        cdp1802.p = 3;
+        cdp1802.r[5] = self.pc as u16;
+        // VX and VY of the instruction, as for every other one
+        cdp1802.r[6] = (registers + (address >> 8 & 0xF)) as u16;
+        cdp1802.r[7] = (registers + (address >> 4 & 0xF)) as u16;
+        cdp1802.r[8] = (self.delay_timer as u16) << 8 | self.sound_timer as u16;
+        cdp1802.r[0xA] = self.i as u16;
+        cdp1802.r[0xB] = display as u16;
+
+        let mut bus = MachineCodeBus {
+            ram: &mut self.ram,
+            keypad: self.keypad,
+            key_latch: &mut self.key_latch,
         };
+        let mut cycles = 0;
+        while cdp1802.p != 4 {
+            if cycles >= MAX_MACHINE_CODE_CYCLES {
+                eprintln!("The machine code at {:03X} didn't return", address);
+                break;
+            }
+            cycles += cdp1802.step(&mut bus);
+        }
+        self.machine_code_cycles = cycles;
+
+        self.registers
+            .copy_from_slice(&self.ram[registers..registers + 16]);
+        self.load_display(display);
+        self.pc = self.cdp1802.r[5] as usize;
+        self.i = self.cdp1802.r[0xA] as usize & 0xFFF;
+        self.delay_timer = (self.cdp1802.r[8] >> 8) as u8;
+        self.sound_timer = self.cdp1802.r[8] as u8;
+    }
+
+    // Packs the screen into memory as the VIP shows it, 8 pixels a byte
+    // with the leftmost one in the high bit.
+    fn store_display(&mut self, address: usize) {
+        for (offset, pixels) in self.vram.chunks(8).enumerate() {
+            self.ram[address + offset] = pixels
+                .iter()
+                .fold(0, |byte, &pixel| byte << 1 | (pixel & 1));
+        }
+    }
+
+    fn load_display(&mut self, address: usize) {
+        for (offset, pixels) in self.vram.chunks_mut(8).enumerate() {
+            let byte = self.ram[address + offset];
+            for (bit, pixel) in pixels.iter_mut().enumerate() {
+                let value = byte >> (7 - bit) & 1;
+                self.vram_flag |= *pixel != value;
+                *pixel = value;
+            }
+        }
     }
 
     fn fetch_instruction(&mut self) -> u16 {
-        let op_code = (self.ram[self.pc] as u16) << 8 | self.ram[self.pc + 1] as u16;
+        let op_code = (self.read(self.pc) as u16) << 8 | self.read(self.pc + 1) as u16;
         self.pc += 2;
         op_code
     }
 }
 
+// The interpreter memory and the keypad, as machine code sees them.
+struct MachineCodeBus<'a> {
+    ram: &'a mut [u8],
+    keypad: [bool; 16],
+    key_latch: &'a mut usize,
+}
+
+impl Bus for MachineCodeBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+    }
+
+    fn output(&mut self, port: usize, value: u8) {
+        if port == 2 {
+            *self.key_latch = (value & 0xF) as usize;
+        }
+    }
+
+    // EF3 is the selected key being held down
+    fn flag(&self, number: usize) -> bool {
+        number == 2 && self.keypad[*self.key_latch]
+    }
+}
+
 pub struct CpuState {
     pub(crate) updated_vram: bool,
-    pub(crate) beep: bool,
+    pub(crate) waiting_for_key: bool,
+    pub(crate) waiting_for_vblank: bool,
+    // how long the instruction takes on the COSMAC VIP, in machine cycles
+    pub(crate) vip_cycles: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::font::FontSet;
+    use crate::hardware::quirks::Platform;
+    use crate::hardware::CHIP8_WIDTH;
+
+    #[test]
+    fn machine_code_sees_and_changes_the_interpreter_state() {
+        let mut cpu = CPU::new(
+            Platform::Vip.quirks(),
+            MemoryLayout::default(),
+            &FontSet::Vip.font(),
+        );
+        // as if 0300 had just been fetched from 200
+        cpu.pc = 0x202;
+        cpu.i = 0x400;
+        cpu.delay_timer = 7;
+        cpu.registers[1] = 0x12;
+        cpu.vram[0] = 1;
+        cpu.vram[7] = 1;
+        cpu.vram[8] = 1;
+        cpu.vram[CHIP8_WIDTH] = 1;
+        let code = [
+            0x1A, // INC RA, I + 1
+            0x15, 0x15, // INC R5 twice, skip the next instruction
+            0x98, // GHI R8, the delay timer
+            0xA8, // PLO R8, into the sound timer
+            0xF8, 0x42, // LDI 42
+            0x57, // STR R7, into V0
+            0xF8, 0xFF, // LDI FF
+            0x5B, // STR RB, the first 8 pixels
+            0xD4, // SEP R4, back to the interpreter
+        ];
+        cpu.ram[0x300..0x300 + code.len()].copy_from_slice(&code);
+
+        cpu.call_machine_code(0x300);
+
+        // the state as the interpreter keeps it, at the end of the memory
+        assert_eq!(cpu.ram[0x1000 - VIP_REGISTERS + 1], 0x12);
+        assert_eq!(cpu.ram[0x1000 - VIP_DISPLAY], 0xFF);
+        assert_eq!(cpu.ram[0x1000 - VIP_DISPLAY + 1], 0x80);
+        assert_eq!(cpu.ram[0x1000 - VIP_DISPLAY + 8], 0x80);
+        // and the changes picked up again
+        assert_eq!(cpu.registers[0], 0x42);
+        assert_eq!(cpu.registers[1], 0x12);
+        assert_eq!(cpu.i, 0x401);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (7, 7));
+        assert!(cpu.vram[..8].iter().all(|&pixel| pixel == 1));
+        assert_eq!((cpu.vram[8], cpu.vram[9]), (1, 0));
+        assert_eq!(cpu.vram[CHIP8_WIDTH], 1);
+        assert!(cpu.vram_flag);
+    }
+
+    #[test]
+    fn machine_code_registers_of_the_instruction() {
+        let mut cpu = CPU::new(
+            Platform::Vip.quirks(),
+            MemoryLayout::default(),
+            &FontSet::Vip.font(),
+        );
+        cpu.registers[0xA] = 0x30;
+        // 0AB0: R6 points at VA and R7 at VB
+        let code = [
+            0x06, // LDN R6
+            0x57, // STR R7, VB = VA
+            0xD4, // SEP R4
+        ];
+        cpu.ram[0xAB0..0xAB0 + code.len()].copy_from_slice(&code);
+        cpu.call_machine_code(0xAB0);
+        assert_eq!(cpu.registers[0xB], 0x30);
+    }
+
+    #[test]
+    fn chip8e_skips_take_the_skip_time() {
+        let mut cpu = CPU::new(
+            Platform::Chip8E.quirks(),
+            MemoryLayout::default(),
+            &FontSet::Vip.font(),
+        );
+        cpu.extension = InstructionSet::Chip8E;
+        // 0188, then 5011 with V0 > V1
+        cpu.ram[0x200..0x208].copy_from_slice(&[0x01, 0x88, 0x00, 0x00, 0x50, 0x11, 0x00, 0x00]);
+        cpu.registers[0] = 1;
+
+        let state = cpu.emulate_cycle([false; 16]);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(state.vip_cycles, timing::vip_cycles(0x0188, 0, true));
+        let state = cpu.emulate_cycle([false; 16]);
+        assert_eq!(cpu.pc, 0x208);
+        assert_eq!(state.vip_cycles, timing::vip_cycles(0x5011, 1, true));
+    }
+
+    // F30A, then 6001 which runs in the cycle that ends the wait
+    fn key_wait_cpu(quirks: Quirks) -> CPU {
+        let mut cpu = CPU::new(quirks, MemoryLayout::default(), &FontSet::Vip.font());
+        cpu.ram[0x200..0x204].copy_from_slice(&[0xF3, 0x0A, 0x60, 0x01]);
+        cpu
+    }
+
+    fn keys(pressed: &[usize]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for &key in pressed {
+            keypad[key] = true;
+        }
+        keypad
+    }
+
+    #[test]
+    fn key_held_before_the_wait_does_not_complete_it() {
+        let mut cpu = key_wait_cpu(Platform::Vip.quirks());
+
+        assert!(cpu.emulate_cycle(keys(&[5])).waiting_for_key);
+        for _ in 0..3 {
+            assert!(cpu.emulate_cycle(keys(&[5])).waiting_for_key);
+        }
+        // nor does letting it go
+        assert!(cpu.emulate_cycle(keys(&[])).waiting_for_key);
+        assert_eq!((cpu.registers[3], cpu.registers[0]), (0, 0));
+    }
+
+    #[test]
+    fn key_wait_completes_on_the_release() {
+        let mut cpu = key_wait_cpu(Platform::Vip.quirks());
+
+        assert!(cpu.emulate_cycle(keys(&[])).waiting_for_key);
+        assert!(cpu.emulate_cycle(keys(&[0xA])).waiting_for_key);
+        assert!(cpu.emulate_cycle(keys(&[0xA])).waiting_for_key);
+        // another key going up doesn't count
+        assert!(cpu.emulate_cycle(keys(&[0xA, 2])).waiting_for_key);
+        assert!(cpu.emulate_cycle(keys(&[0xA])).waiting_for_key);
+        assert!(!cpu.emulate_cycle(keys(&[])).waiting_for_key);
+        assert_eq!(cpu.registers[3], 0xA);
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn key_wait_completes_on_the_press_without_the_release_quirk() {
+        let mut quirks = Platform::Vip.quirks();
+        quirks.key_wait_release = false;
+        let mut cpu = key_wait_cpu(quirks);
+
+        assert!(cpu.emulate_cycle(keys(&[])).waiting_for_key);
+        assert!(!cpu.emulate_cycle(keys(&[7])).waiting_for_key);
+        assert_eq!(cpu.registers[3], 7);
+    }
 }
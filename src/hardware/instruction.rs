@@ -1,14 +1,19 @@
+use super::color::{ZONE_HEIGHT, ZONE_WIDTH};
 use super::cpu;
+use super::font::BIG_GLYPH_SIZE;
+use super::rom::InstructionSet;
 use crate::hardware::cpu::CPU;
+use crate::hardware::{CHIP8_HEIGHT, CHIP8_WIDTH};
 use rand::Rng;
 
 pub struct Instruction {
-    op_code: u16,
     execution: Execution,
 }
 
 impl Instruction {
-    pub fn decode(op_code: &u16) -> Instruction {
+    // The opcodes of the extension come first, CHIP-8E and CHIP-8X give
+    // a few CHIP-8 and SUPER-CHIP ones another meaning.
+    pub fn decode(op_code: &u16, extension: InstructionSet) -> Instruction {
         let decoded_op = (
             ((op_code & 0xF000) >> 12) as u8,
             ((op_code & 0x0F00) >> 8) as u8,
@@ -21,10 +26,36 @@ impl Instruction {
         let x = decoded_op.1 as usize;
         let y = decoded_op.2 as usize;
         let n = decoded_op.3 as usize;
+        let chip8e = extension == InstructionSet::Chip8E;
+        let chip8x = extension == InstructionSet::Chip8X;
 
         let execution: Box<dyn Fn(&mut CPU)> = match decoded_op {
+            (0x00, 0x00, 0x0e, 0x0d) if chip8e => Box::new(Instruction::stop),
+            (0x00, 0x00, 0x0f, 0x02) if chip8e => Box::new(Instruction::nop),
+            (0x00, 0x01, 0x05, 0x01) if chip8e => Box::new(Instruction::wait_delay),
+            (0x00, 0x01, 0x08, 0x08) if chip8e => Box::new(Instruction::skip),
+            (0x05, _, _, 0x01) if chip8e => Instruction::bgtr(x, y),
+            (0x05, _, _, 0x02) if chip8e => Instruction::store_range(x, y),
+            (0x05, _, _, 0x03) if chip8e => Instruction::load_range(x, y),
+            (0x0b, 0x0b, _, _) if chip8e => Instruction::jump_back(kk),
+            (0x0b, 0x0f, _, _) if chip8e => Instruction::jump_forward(kk),
+            (0x0f, _, 0x00, 0x03) if chip8e => Box::new(Instruction::nop), // OUT 3: nothing is connected
+            (0x0f, _, 0x01, 0x0b) if chip8e => Instruction::skip_bytes(x),
+            (0x0f, _, 0x04, 0x0f) if chip8e => Instruction::delay_wait(x),
+            (0x0f, _, 0x0e, 0x03) if chip8e => Instruction::input(x),
+            (0x0f, _, 0x0e, 0x07) if chip8e => Instruction::input(x),
+            (0x00, 0x02, 0x0a, 0x00) if chip8x => Box::new(Instruction::next_background),
+            (0x05, _, _, 0x01) if chip8x => Instruction::add_nibbles(x, y),
+            (0x0b, _, _, 0x00) if chip8x => Instruction::color_zones(x, y),
+            (0x0b, _, _, _) if chip8x => Instruction::color_sprite(x, y, n),
+            // the second keypad reads the same keys as the first one
+            (0x0e, _, 0x0f, 0x02) if chip8x => Instruction::key_pressed(x),
+            (0x0e, _, 0x0f, 0x05) if chip8x => Instruction::key_released(x),
+            (0x0f, _, 0x0f, 0x08) if chip8x => Box::new(Instruction::nop), // the tone pitch isn't emulated
+            (0x0f, _, 0x0f, 0x0b) if chip8x => Instruction::input(x),
             (0x00, 0x00, 0x0e, 0x00) => Box::new(Instruction::cls), // CLS: Clear the display.
             (0x00, 0x00, 0x0e, 0x0e) => Box::new(Instruction::ret),
+            (0x00, _, _, _) => Instruction::machine_code(nnn),
             (0x01, _, _, _) => Instruction::jump(nnn),
             (0x02, _, _, _) => Instruction::call(nnn),
             (0x03, _, _, _) => Instruction::beq(x, kk),
@@ -54,16 +85,14 @@ impl Instruction {
             (0x0f, _, 0x01, 0x08) => Instruction::sound_timer(x),
             (0x0f, _, 0x01, 0x0e) => Instruction::inc_mem(x),
             (0x0f, _, 0x02, 0x09) => Instruction::load_sprite(x),
+            (0x0f, _, 0x03, 0x00) => Instruction::load_big_sprite(x),
             (0x0f, _, 0x03, 0x03) => Instruction::bcd(x),
             (0x0f, _, 0x05, 0x05) => Instruction::reg_dump(x),
             (0x0f, _, 0x06, 0x05) => Instruction::reg_load(x),
             _ => Box::new(Instruction::unknown(op_code)),
         };
 
-        Instruction {
-            op_code: op_code.clone(),
-            execution,
-        }
+        Instruction { execution }
     }
 
     pub fn execute(&self, cpu: &mut cpu::CPU) {
@@ -78,9 +107,24 @@ impl Instruction {
     }
 
     fn ret(cpu: &mut cpu::CPU) {
+        if cpu.sp == 0 {
+            eprintln!("Stack underflow at {:03X}", cpu.pc - 2);
+            return;
+        }
         cpu.sp -= 1;
-        let return_point = cpu.stack[cpu.sp];
-        cpu.pc = return_point as usize;
+        cpu.pc = cpu.stack[cpu.sp];
+    }
+
+    // SYS addr
+    // Call the machine code subroutine at nnn, with the machine code quirk.
+    fn machine_code(nnn: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            if cpu.quirks.machine_code {
+                cpu.call_machine_code(nnn);
+            } else {
+                eprintln!("Unknown OP_CODE: {:#04X}", nnn);
+            }
+        })
     }
 
     fn jump(nnn: usize) -> Box<dyn Fn(&mut CPU)> {
@@ -91,6 +135,10 @@ impl Instruction {
 
     fn call(nnn: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
+            if cpu.sp == cpu.stack.len() {
+                eprintln!("Stack overflow at {:03X}", cpu.pc - 2);
+                return;
+            }
             cpu.stack[cpu.sp] = cpu.pc;
             cpu.sp += 1;
             cpu.pc = nnn;
@@ -246,27 +294,44 @@ impl Instruction {
     // stored in I. These bytes are then displayed as sprites on screen at
     // coordinates (Vx, Vy). Sprites are XORed onto the existing screen.
     // If this causes any pixels to be erased, VF is set to 1, otherwise
-    // it is set to 0. If the sprite is positioned so part of it is outside
+    // it is set to 0. The starting coordinates always wrap around the
+    // display. If the sprite is positioned so part of it is outside
     // the coordinates of the display, it wraps around to the opposite side
-    // of the screen.
+    // of the screen, or it is cut off with the sprite clipping quirk.
+    // With the display wait quirk the CPU then stalls until the next frame.
     fn draw(x: usize, y: usize, n: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
+            let start_x = cpu.registers[x] as usize % CHIP8_WIDTH;
+            let start_y = cpu.registers[y] as usize % CHIP8_HEIGHT;
             cpu.registers[15] = 0;
             for i in 0..n {
-                let ram_address = cpu.i + i;
-                let y = 64 * ((cpu.registers[y] as usize + i) % 32);
+                let mut y = start_y + i;
+                if y >= CHIP8_HEIGHT {
+                    if cpu.quirks.clip_sprites {
+                        break;
+                    }
+                    y %= CHIP8_HEIGHT;
+                }
+                let sprite_row = cpu.read(cpu.i + i);
                 for b in 0..8 {
-                    let x = (cpu.registers[x] as usize + b) % 64;
-
-                    let vram_address = x + y; //TODO refactor
-                    let current_pixel = cpu.vram[vram_address];
-                    let mask: u8 = (1 << 7 - b) as u8;
-                    let new_pixel = current_pixel ^ (cpu.ram[ram_address] & mask > 0) as u8;
-                    cpu.vram[vram_address] = new_pixel;
-                    cpu.registers[15] |= (current_pixel > 0 && new_pixel == 0) as u8;
+                    let mut x = start_x + b;
+                    if x >= CHIP8_WIDTH {
+                        if cpu.quirks.clip_sprites {
+                            break;
+                        }
+                        x %= CHIP8_WIDTH;
+                    }
+                    if sprite_row & (0x80 >> b) == 0 {
+                        continue;
+                    }
+
+                    let vram_address = x + y * CHIP8_WIDTH;
+                    cpu.registers[15] |= cpu.vram[vram_address];
+                    cpu.vram[vram_address] ^= 1;
                 }
             }
             cpu.vram_flag = true;
+            cpu.await_vblank = cpu.quirks.display_wait;
         })
     }
 
@@ -295,6 +360,7 @@ impl Instruction {
     fn get_key(x: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
             cpu.keypad_dst = x;
+            cpu.awaited_key = None;
             cpu.await_keypad = true;
         })
     }
@@ -321,22 +387,30 @@ impl Instruction {
 
     fn load_sprite(x: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
-            cpu.i = (cpu.registers[x] as usize) * 5;
+            cpu.i = cpu.layout.font_base + (cpu.registers[x] as usize) * 5;
+        })
+    }
+
+    // LD HF, Vx
+    // Set I = location of the big SUPER-CHIP sprite for digit Vx.
+    fn load_big_sprite(x: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.i = cpu.layout.big_font_base + (cpu.registers[x] as usize & 0xF) * BIG_GLYPH_SIZE;
         })
     }
 
     fn bcd(x: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
-            cpu.ram[cpu.i] = cpu.registers[x] / 100;
-            cpu.ram[cpu.i + 1] = (cpu.registers[x] % 100) / 10;
-            cpu.ram[cpu.i + 2] = cpu.registers[x] % 10;
+            cpu.write(cpu.i, cpu.registers[x] / 100);
+            cpu.write(cpu.i + 1, (cpu.registers[x] % 100) / 10);
+            cpu.write(cpu.i + 2, cpu.registers[x] % 10);
         })
     }
 
     fn reg_dump(x: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
             for i in 0..x + 1 {
-                cpu.ram[cpu.i + i] = cpu.registers[i];
+                cpu.write(cpu.i + i, cpu.registers[i]);
             }
         })
     }
@@ -344,11 +418,145 @@ impl Instruction {
     fn reg_load(x: usize) -> Box<dyn Fn(&mut CPU)> {
         Box::new(move |cpu: &mut cpu::CPU| {
             for i in 0..x + 1 {
-                cpu.registers[i] = cpu.ram[cpu.i + i];
+                cpu.registers[i] = cpu.read(cpu.i + i);
+            }
+        })
+    }
+
+    // Halt the program, CHIP-8E.
+    fn stop(cpu: &mut cpu::CPU) {
+        cpu.pc -= 2;
+        cpu.await_vblank = true;
+    }
+
+    // Wait until the delay timer is 0, CHIP-8E.
+    fn wait_delay(cpu: &mut cpu::CPU) {
+        cpu.await_delay = true;
+    }
+
+    // Skip the next instruction, CHIP-8E.
+    fn skip(cpu: &mut cpu::CPU) {
+        cpu.pc += 2;
+    }
+
+    // Skip the next instruction if Vx > Vy, CHIP-8E.
+    fn bgtr(x: usize, y: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            if cpu.registers[x] > cpu.registers[y] {
+                cpu.pc += 2; //skip
             }
         })
     }
 
+    // Store Vx to Vy in memory from I, and move I past them, CHIP-8E.
+    fn store_range(x: usize, y: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            for register in x..=y {
+                cpu.write(cpu.i, cpu.registers[register]);
+                cpu.i += 1;
+            }
+        })
+    }
+
+    // Load Vx to Vy from memory from I, and move I past them, CHIP-8E.
+    fn load_range(x: usize, y: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            for register in x..=y {
+                cpu.registers[register] = cpu.read(cpu.i);
+                cpu.i += 1;
+            }
+        })
+    }
+
+    // Jump nn bytes back or forward from the next instruction, CHIP-8E.
+    fn jump_back(nn: u8) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.pc = cpu.pc.wrapping_sub(nn as usize) & 0xFFF;
+        })
+    }
+
+    fn jump_forward(nn: u8) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.pc = (cpu.pc + nn as usize) & 0xFFF;
+        })
+    }
+
+    // Skip the next Vx bytes, CHIP-8E.
+    fn skip_bytes(x: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.pc += cpu.registers[x] as usize;
+        })
+    }
+
+    // Set the delay timer to Vx and wait until it is 0, CHIP-8E.
+    fn delay_wait(x: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.delay_timer = cpu.registers[x];
+            cpu.await_delay = true;
+        })
+    }
+
+    // Read Vx from an input port. Nothing is connected, so it reads 0
+    // without waiting for the strobe.
+    fn input(x: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            cpu.registers[x] = 0;
+        })
+    }
+
+    // Step to the next background colour, CHIP-8X.
+    fn next_background(cpu: &mut cpu::CPU) {
+        cpu.colors.next_background();
+        cpu.vram_flag = true;
+    }
+
+    // Add Vy to Vx, each half on its own and modulo 8, CHIP-8X.
+    fn add_nibbles(x: usize, y: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            let (vx, vy) = (cpu.registers[x], cpu.registers[y]);
+            let high = ((vx >> 4) + (vy >> 4)) & 0x7;
+            let low = ((vx & 0xF) + (vy & 0xF)) & 0x7;
+            cpu.registers[x] = high << 4 | low;
+        })
+    }
+
+    // Colour the zones from column Vx and row Vx+1 with Vy, CHIP-8X. The
+    // high nibbles of Vx and Vx+1 are the number of zones more to colour
+    // to the right and below.
+    fn color_zones(x: usize, y: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            let horizontal = cpu.registers[x] as usize;
+            let vertical = cpu.registers[(x + 1) & 0xF] as usize;
+            cpu.colors.fill(
+                horizontal & 0xF,
+                vertical & 0xF,
+                (horizontal >> 4) + 1,
+                (vertical >> 4) + 1,
+                cpu.registers[y],
+            );
+            cpu.vram_flag = true;
+        })
+    }
+
+    // Colour the zones an 8xN sprite at (Vx, Vy) would cover with Vx+1,
+    // CHIP-8X.
+    fn color_sprite(x: usize, y: usize, n: usize) -> Box<dyn Fn(&mut CPU)> {
+        Box::new(move |cpu: &mut cpu::CPU| {
+            let left = cpu.registers[x] as usize % CHIP8_WIDTH;
+            let top = cpu.registers[y] as usize % CHIP8_HEIGHT;
+            let column = left / ZONE_WIDTH;
+            let row = top / ZONE_HEIGHT;
+            cpu.colors.fill(
+                column,
+                row,
+                (left + 7) / ZONE_WIDTH - column + 1,
+                (top + n - 1) / ZONE_HEIGHT - row + 1,
+                cpu.registers[(x + 1) & 0xF],
+            );
+            cpu.vram_flag = true;
+        })
+    }
+
     fn nop(_cpu: &mut cpu::CPU) {}
 
     fn unknown(op_code: &u16) -> fn(&mut cpu::CPU) {
@@ -358,3 +566,115 @@ impl Instruction {
 }
 
 type Execution = Box<dyn Fn(&mut CPU)>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::font::FontSet;
+    use crate::hardware::memory::MemoryLayout;
+    use crate::hardware::quirks::Platform;
+
+    // Draws `rows` rows of 8 pixels with D01N at V0, V1.
+    fn draw(cpu: &mut CPU, x: u8, y: u8, rows: u16) {
+        cpu.registers[0] = x;
+        cpu.registers[1] = y;
+        Instruction::decode(&(0xD010 | rows), InstructionSet::Chip8).execute(cpu);
+    }
+
+    fn sprite_cpu(clip_sprites: bool) -> CPU {
+        let mut quirks = Platform::Chip8.quirks();
+        quirks.clip_sprites = clip_sprites;
+        let mut cpu = CPU::new(quirks, MemoryLayout::default(), &FontSet::Vip.font());
+        cpu.i = 0x300;
+        for row in 0..15 {
+            cpu.ram[0x300 + row] = 0xFF;
+        }
+        cpu
+    }
+
+    fn lit(cpu: &CPU) -> Vec<(usize, usize)> {
+        (0..CHIP8_WIDTH * CHIP8_HEIGHT)
+            .filter(|&pixel| cpu.vram[pixel] == 1)
+            .map(|pixel| (pixel % CHIP8_WIDTH, pixel / CHIP8_WIDTH))
+            .collect()
+    }
+
+    fn block(xs: std::ops::Range<usize>, ys: std::ops::Range<usize>) -> Vec<(usize, usize)> {
+        sorted(ys.flat_map(|y| xs.clone().map(move |x| (x, y))).collect())
+    }
+
+    fn sorted(mut pixels: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        pixels.sort_by_key(|&(x, y)| (y, x));
+        pixels
+    }
+
+    #[test]
+    fn sprites_wrap_at_the_right_and_bottom_edges() {
+        let mut cpu = sprite_cpu(false);
+        draw(&mut cpu, 60, 30, 4);
+
+        let mut expected = block(60..64, 30..32);
+        expected.extend(block(0..4, 30..32));
+        expected.extend(block(60..64, 0..2));
+        expected.extend(block(0..4, 0..2));
+        assert_eq!(lit(&cpu), sorted(expected));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn sprites_clip_at_the_right_and_bottom_edges() {
+        let mut cpu = sprite_cpu(true);
+        draw(&mut cpu, 60, 30, 4);
+
+        assert_eq!(lit(&cpu), block(60..64, 30..32));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn start_coordinates_wrap_when_clipping() {
+        let mut cpu = sprite_cpu(true);
+        // (124, 60) starts at (60, 28), the body is clipped
+        draw(&mut cpu, 124, 60, 8);
+        assert_eq!(lit(&cpu), block(60..64, 28..32));
+
+        // (70, 40) starts at (6, 8) and fits on the screen
+        let mut cpu = sprite_cpu(true);
+        draw(&mut cpu, 70, 40, 8);
+        assert_eq!(lit(&cpu), block(6..14, 8..16));
+    }
+
+    #[test]
+    fn collision_sets_vf_in_both_modes() {
+        for &clip_sprites in &[false, true] {
+            let mut cpu = sprite_cpu(clip_sprites);
+            draw(&mut cpu, 60, 28, 4);
+            draw(&mut cpu, 62, 29, 1);
+
+            assert_eq!(cpu.registers[15], 1);
+            // the overlapping pixels are erased
+            assert_eq!(cpu.vram[62 + 29 * CHIP8_WIDTH], 0);
+            assert_eq!(cpu.vram[60 + 29 * CHIP8_WIDTH], 1);
+
+            draw(&mut cpu, 0, 0, 1);
+            assert_eq!(cpu.registers[15], 0);
+        }
+    }
+
+    #[test]
+    fn clipped_pixels_do_not_collide() {
+        let mut cpu = sprite_cpu(true);
+        cpu.vram[0] = 1;
+        draw(&mut cpu, 60, 30, 4);
+
+        assert_eq!(cpu.registers[15], 0);
+        assert_eq!(cpu.vram[0], 1);
+
+        // wrapped around, the sprite hits the pixel
+        let mut cpu = sprite_cpu(false);
+        cpu.vram[0] = 1;
+        draw(&mut cpu, 60, 30, 4);
+
+        assert_eq!(cpu.registers[15], 1);
+        assert_eq!(cpu.vram[0], 0);
+    }
+}
@@ -0,0 +1,201 @@
+use super::font::{BIG_GLYPH_SIZE, FONT_SIZE};
+
+// addresses are 12 bits wide, more memory can't be reached
+const MAX_RAM_SIZE: usize = 0x1000;
+// the smallest memory that still leaves room for the interpreter area
+const MIN_RAM_SIZE: usize = 0x200;
+
+// Where the interpreter puts things in memory. Machines other than the
+// COSMAC VIP load programs at other addresses and have more or less
+// memory and stack.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryLayout {
+    // address the program is loaded at and starts from
+    pub start: usize,
+    // address of the hexadecimal font used by FX29
+    pub font_base: usize,
+    // address of the big font used by FX30
+    pub big_font_base: usize,
+    pub ram_size: usize,
+    // number of subroutine calls that can be nested
+    pub stack_depth: usize,
+}
+
+impl Default for MemoryLayout {
+    fn default() -> Self {
+        MemoryLayout {
+            start: 0x200,
+            font_base: 0x000,
+            big_font_base: 0x050,
+            ram_size: MAX_RAM_SIZE,
+            stack_depth: 16,
+        }
+    }
+}
+
+impl MemoryLayout {
+    // Room left for the program after the start address.
+    pub fn max_rom_size(&self) -> usize {
+        self.ram_size.saturating_sub(self.start)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ram_size < MIN_RAM_SIZE || self.ram_size > MAX_RAM_SIZE {
+            return Err(format!(
+                "the RAM size must be between {:#X} and {:#X} bytes",
+                MIN_RAM_SIZE, MAX_RAM_SIZE
+            ));
+        }
+        if self.start >= self.ram_size {
+            return Err(format!(
+                "the load address {:#X} is outside of the {} bytes of RAM",
+                self.start, self.ram_size
+            ));
+        }
+        if self.font_base + FONT_SIZE > self.ram_size {
+            return Err(format!(
+                "the font at {:#X} doesn't fit in the {} bytes of RAM",
+                self.font_base, self.ram_size
+            ));
+        }
+        if self.big_font_base + BIG_GLYPH_SIZE * 16 > self.ram_size {
+            return Err(format!(
+                "the big font at {:#X} doesn't fit in the {} bytes of RAM",
+                self.big_font_base, self.ram_size
+            ));
+        }
+        if self.stack_depth == 0 {
+            return Err(String::from("the stack needs at least one level"));
+        }
+        // the program may grow up to the end of the memory
+        let areas = [
+            ("font", self.font_base, FONT_SIZE),
+            ("big font", self.big_font_base, BIG_GLYPH_SIZE * 16),
+            ("program", self.start, self.ram_size - self.start),
+        ];
+        for (index, &(name, base, size)) in areas.iter().enumerate() {
+            for &(other, other_base, other_size) in &areas[index + 1..] {
+                if base < other_base + other_size && other_base < base + size {
+                    return Err(format!(
+                        "the {} at {:#X}-{:#X} overlaps the {} at {:#X}-{:#X}",
+                        name,
+                        base,
+                        base + size - 1,
+                        other,
+                        other_base,
+                        other_base + other_size - 1
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Parses an address or size, in hexadecimal with a 0x prefix or in
+// decimal, e.g. "0x600" or "1536".
+pub fn parse_number(s: &str) -> Result<usize, String> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|_| format!("invalid number '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::quirks::Platform;
+
+    #[test]
+    fn platform_layouts_are_valid() {
+        for platform in &[
+            Platform::Chip8,
+            Platform::Vip,
+            Platform::Eti660,
+            Platform::Chip8E,
+            Platform::Chip8X,
+        ] {
+            assert_eq!(platform.memory_layout().validate(), Ok(()), "{}", platform);
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_outside_the_limits() {
+        for &ram_size in &[0x1FF, 0x1001] {
+            let layout = MemoryLayout {
+                start: 0x100,
+                ram_size,
+                ..MemoryLayout::default()
+            };
+            assert!(layout.validate().is_err());
+        }
+        let layout = MemoryLayout {
+            stack_depth: 0,
+            ..MemoryLayout::default()
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_areas_past_the_end_of_the_memory() {
+        let layout = MemoryLayout {
+            ram_size: 0x800,
+            start: 0x800,
+            ..MemoryLayout::default()
+        };
+        assert!(layout.validate().is_err());
+
+        let layout = MemoryLayout {
+            font_base: 0xFB0,
+            ..MemoryLayout::default()
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_overlapping_fonts() {
+        let layout = MemoryLayout {
+            font_base: 0x40,
+            ..MemoryLayout::default()
+        };
+
+        assert_eq!(
+            layout.validate(),
+            Err(String::from(
+                "the font at 0x40-0x8F overlaps the big font at 0x50-0xEF"
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_fonts_in_the_program() {
+        let layout = MemoryLayout {
+            font_base: 0x200,
+            ..MemoryLayout::default()
+        };
+        assert_eq!(
+            layout.validate(),
+            Err(String::from(
+                "the font at 0x200-0x24F overlaps the program at 0x200-0xFFF"
+            ))
+        );
+
+        // a program loaded over the big font
+        let layout = MemoryLayout {
+            start: 0x80,
+            ..MemoryLayout::default()
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn fonts_may_follow_each_other() {
+        let layout = MemoryLayout {
+            font_base: 0x1B0,
+            big_font_base: 0x110,
+            ..MemoryLayout::default()
+        };
+        assert_eq!(layout.validate(), Ok(()));
+    }
+}
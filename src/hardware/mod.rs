@@ -1,6 +1,14 @@
+mod cdp1802;
+pub mod color;
 pub mod cpu;
-mod font;
+pub mod font;
 mod instruction;
+pub mod memory;
+pub mod quirks;
+pub mod rom;
+pub mod timing;
+pub mod vip;
 
 pub const CHIP8_WIDTH: usize = 64;
 pub const CHIP8_HEIGHT: usize = 32;
+pub const CHIP8_TIMER_RATE: f64 = 60.;
@@ -0,0 +1,185 @@
+use super::font::FontSet;
+use super::memory::MemoryLayout;
+use super::rom::InstructionSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// Behaviours that differ between CHIP-8 interpreters.
+#[derive(Clone, Copy, Debug)]
+pub struct Quirks {
+    // FX0A completes when the key is released instead of when it is pressed.
+    pub key_wait_release: bool,
+    // DXYN waits for the next vertical blank, i.e. the next 60Hz frame.
+    pub display_wait: bool,
+    // Sprites are cut off at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    // 0NNN runs RCA 1802 machine code, as on the COSMAC VIP.
+    pub machine_code: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::Chip8.quirks()
+    }
+}
+
+impl Quirks {
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        match name {
+            "key-release" => self.key_wait_release = enabled,
+            "display-wait" => self.display_wait = enabled,
+            "clip" => self.clip_sprites = enabled,
+            "machine-code" => self.machine_code = enabled,
+            _ => return Err(format!("unknown quirk '{}'", name)),
+        }
+        Ok(())
+    }
+}
+
+// Quirk settings given on the command line as a comma separated list,
+// e.g. "key-release=off". A quirk name on its own turns it on.
+#[derive(Clone, Debug, Default)]
+pub struct QuirkOverrides(Vec<(String, bool)>);
+
+impl QuirkOverrides {
+    pub fn apply(&self, quirks: &mut Quirks) {
+        for (name, enabled) in &self.0 {
+            // names were validated while parsing
+            quirks.set(name, *enabled).unwrap();
+        }
+    }
+}
+
+impl FromStr for QuirkOverrides {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = Vec::new();
+        for setting in s.split(',').map(str::trim).filter(|q| !q.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            let enabled = match parts.next() {
+                None | Some("on") | Some("true") | Some("1") => true,
+                Some("off") | Some("false") | Some("0") => false,
+                Some(value) => return Err(format!("invalid value '{}' for {}", value, name)),
+            };
+            Quirks::default().set(name, enabled)?;
+            overrides.push((name.to_string(), enabled));
+        }
+        Ok(QuirkOverrides(overrides))
+    }
+}
+
+// The interpreter to emulate, each one comes with its own set of quirks
+// and memory layout.
+#[derive(Clone, Copy, Debug)]
+pub enum Platform {
+    Chip8,
+    Vip,
+    // the ETI 660 kit computer, which loads programs at 0x600
+    Eti660,
+    // the VIP interpreters extended with more instructions
+    Chip8E,
+    Chip8X,
+}
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                key_wait_release: true,
+                display_wait: false,
+                clip_sprites: false,
+                machine_code: false,
+            },
+            Platform::Vip => Quirks {
+                key_wait_release: true,
+                display_wait: true,
+                clip_sprites: true,
+                machine_code: true,
+            },
+            // an 1802 too, but its interpreter keeps things elsewhere
+            Platform::Eti660 => Quirks {
+                machine_code: false,
+                ..Platform::Vip.quirks()
+            },
+            Platform::Chip8E => Platform::Vip.quirks(),
+            // the bigger interpreter and the colour board move things around
+            Platform::Chip8X => Quirks {
+                machine_code: false,
+                ..Platform::Vip.quirks()
+            },
+        }
+    }
+
+    pub fn memory_layout(self) -> MemoryLayout {
+        match self {
+            Platform::Chip8 => MemoryLayout::default(),
+            // the VIP interpreter keeps room for 12 return addresses
+            Platform::Vip | Platform::Chip8E => MemoryLayout {
+                stack_depth: 12,
+                ..MemoryLayout::default()
+            },
+            Platform::Eti660 => MemoryLayout {
+                start: 0x600,
+                ..MemoryLayout::default()
+            },
+            // its interpreter takes up 768 bytes
+            Platform::Chip8X => MemoryLayout {
+                start: 0x300,
+                ..Platform::Vip.memory_layout()
+            },
+        }
+    }
+
+    pub fn font_set(self) -> FontSet {
+        match self {
+            Platform::Chip8 => FontSet::Chip8,
+            Platform::Vip | Platform::Chip8E | Platform::Chip8X => FontSet::Vip,
+            Platform::Eti660 => FontSet::Eti660,
+        }
+    }
+
+    // The instructions the interpreter has on top of CHIP-8, or CHIP-8
+    // itself when it has none.
+    pub fn extension(self) -> InstructionSet {
+        match self {
+            Platform::Chip8E => InstructionSet::Chip8E,
+            Platform::Chip8X => InstructionSet::Chip8X,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    // Whether the interpreter runs the instructions of the given set.
+    pub fn supports(self, instruction_set: InstructionSet) -> bool {
+        instruction_set == InstructionSet::Chip8 || instruction_set == self.extension()
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "chip8",
+            Platform::Vip => "vip",
+            Platform::Eti660 => "eti660",
+            Platform::Chip8E => "chip8e",
+            Platform::Chip8X => "chip8x",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "vip" | "cosmac-vip" => Ok(Platform::Vip),
+            "eti660" | "eti-660" => Ok(Platform::Eti660),
+            "chip8e" | "chip-8e" => Ok(Platform::Chip8E),
+            "chip8x" | "chip-8x" => Ok(Platform::Chip8X),
+            _ => Err(format!("unknown platform '{}'", s)),
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use super::memory::MemoryLayout;
+use super::quirks::{Platform, Quirks};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+// The instruction sets a ROM can be written for, from the original
+// interpreter to its extensions.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum InstructionSet {
+    Chip8,
+    // the extra skips, loads and stores of CHIP-8E
+    Chip8E,
+    // the colours and second keypad of CHIP-8X
+    Chip8X,
+    SuperChip,
+    XoChip,
+    // 0NNN, a call to a machine code routine of the host computer
+    MachineCode,
+}
+
+impl Display for InstructionSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InstructionSet::Chip8 => "CHIP-8",
+            InstructionSet::Chip8E => "CHIP-8E",
+            InstructionSet::Chip8X => "CHIP-8X",
+            InstructionSet::SuperChip => "SUPER-CHIP",
+            InstructionSet::XoChip => "XO-CHIP",
+            InstructionSet::MachineCode => "machine code",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// What was found out about a ROM before running it.
+pub struct RomInfo {
+    pub size: usize,
+    pub sha1: String,
+    // the newest instruction set the ROM uses
+    pub instruction_set: InstructionSet,
+    pub warnings: Vec<String>,
+}
+
+impl Display for RomInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes, SHA-1 {}, {}",
+            self.size, self.sha1, self.instruction_set
+        )
+    }
+}
+
+// Refuses ROMs that can't run at all, and looks for the instructions
+// the platform doesn't support in the code reachable from the start.
+// Machine code is run with the machine code quirk whatever the platform.
+pub fn check(
+    rom: &[u8],
+    platform: Platform,
+    quirks: &Quirks,
+    layout: &MemoryLayout,
+) -> io::Result<RomInfo> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let max_size = layout.max_rom_size();
+    if rom.is_empty() {
+        return Err(invalid(String::from("the ROM is empty")));
+    }
+    if rom.len() > max_size {
+        return Err(invalid(format!(
+            "the ROM is {} bytes, only {} fit in memory after {:03X}",
+            rom.len(),
+            max_size,
+            layout.start
+        )));
+    }
+
+    let mut warnings = Vec::new();
+    // instructions are 2 bytes, whatever the code reaches
+    if rom.len() % 2 == 1 {
+        warnings.push(format!(
+            "the ROM is {} bytes, an odd size, so its last byte is half an instruction",
+            rom.len()
+        ));
+    }
+    let end = layout.start + rom.len();
+    let extension = platform.extension();
+    let (instructions, outside) = reachable_instructions(rom, layout, extension);
+    for address in outside {
+        warnings.push(if address < layout.start {
+            format!("jumps to {:03X}, before the start of the program", address)
+        } else if address + 1 == end {
+            format!(
+                "the instruction at {:03X} is cut off by the end of the ROM",
+                address
+            )
+        } else {
+            format!(
+                "runs past the end of the ROM into empty memory at {:03X}",
+                address
+            )
+        });
+    }
+
+    let mut instruction_set = InstructionSet::Chip8;
+    for (address, op_code) in instructions {
+        let set = match classify(op_code, extension) {
+            Some(set) => set,
+            None => {
+                warnings.push(format!(
+                    "unknown instruction {:04X} at {:03X}",
+                    op_code, address
+                ));
+                continue;
+            }
+        };
+        if set > instruction_set && set != InstructionSet::MachineCode {
+            instruction_set = set;
+        }
+        let machine_code = set == InstructionSet::MachineCode && quirks.machine_code;
+        if !platform.supports(set) && !machine_code {
+            warnings.push(format!(
+                "{} instruction {:04X} at {:03X} isn't supported by the {} platform",
+                set, op_code, address, platform
+            ));
+        }
+    }
+
+    Ok(RomInfo {
+        size: rom.len(),
+        sha1: sha1_smol::Sha1::from(rom).digest().to_string(),
+        instruction_set,
+        warnings,
+    })
+}
+
+// Which instruction set an opcode comes from, None if it is in none.
+// The extensions of the platform come first, as some of their opcodes
+// mean something else in the other sets.
+fn classify(op_code: u16, extension: InstructionSet) -> Option<InstructionSet> {
+    let nibbles = (
+        (op_code >> 12) as u8,
+        (op_code >> 8 & 0xF) as u8,
+        (op_code >> 4 & 0xF) as u8,
+        (op_code & 0xF) as u8,
+    );
+    let extended = match extension {
+        InstructionSet::Chip8E => matches!(
+            nibbles,
+            (0x0, 0x0, 0xE, 0xD)
+                | (0x0, 0x0, 0xF, 0x2)
+                | (0x0, 0x1, 0x5, 0x1)
+                | (0x0, 0x1, 0x8, 0x8)
+                | (0x5, _, _, 0x1..=0x3)
+                | (0xB, 0xB, _, _)
+                | (0xB, 0xF, _, _)
+                | (0xF, _, 0x0, 0x3)
+                | (0xF, _, 0x1, 0xB)
+                | (0xF, _, 0x4, 0xF)
+                | (0xF, _, 0xE, 0x3)
+                | (0xF, _, 0xE, 0x7)
+        ),
+        InstructionSet::Chip8X => matches!(
+            nibbles,
+            (0x0, 0x2, 0xA, 0x0)
+                | (0x5, _, _, 0x1)
+                | (0xB, _, _, _)
+                | (0xE, _, 0xF, 0x2)
+                | (0xE, _, 0xF, 0x5)
+                | (0xF, _, 0xF, 0x8)
+                | (0xF, _, 0xF, 0xB)
+        ),
+        _ => false,
+    };
+    if extended {
+        return Some(extension);
+    }
+    let set = match nibbles {
+        (0x0, 0x0, 0xE, 0x0) | (0x0, 0x0, 0xE, 0xE) => InstructionSet::Chip8,
+        (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB..=0xF) => InstructionSet::SuperChip,
+        (0x0, 0x0, 0xD, _) => InstructionSet::XoChip,
+        (0x0, _, _, _) => InstructionSet::MachineCode,
+        (0x1..=0x4, _, _, _) => InstructionSet::Chip8,
+        (0x5, _, _, 0x0) => InstructionSet::Chip8,
+        (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => InstructionSet::XoChip,
+        (0x6, _, _, _) | (0x7, _, _, _) => InstructionSet::Chip8,
+        (0x8, _, _, 0x0..=0x7) | (0x8, _, _, 0xE) => InstructionSet::Chip8,
+        (0x9, _, _, 0x0) => InstructionSet::Chip8,
+        (0xA..=0xC, _, _, _) => InstructionSet::Chip8,
+        (0xD, _, _, 0x0) => InstructionSet::SuperChip,
+        (0xD, _, _, _) => InstructionSet::Chip8,
+        (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => InstructionSet::Chip8,
+        (0xF, 0x0, 0x0, 0x0) | (0xF, 0x0, 0x0, 0x2) | (0xF, _, 0x0, 0x1) => InstructionSet::XoChip,
+        (0xF, _, 0x3, 0xA) => InstructionSet::XoChip,
+        (0xF, _, 0x3, 0x0) | (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => InstructionSet::SuperChip,
+        (0xF, _, 0x0, 0x7) | (0xF, _, 0x0, 0xA) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) => {
+            InstructionSet::Chip8
+        }
+        (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x3) => InstructionSet::Chip8,
+        (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => InstructionSet::Chip8,
+        _ => return None,
+    };
+    Some(set)
+}
+
+// Follows the jumps, calls and skips from the start of the program, so
+// that the sprites and other data in the ROM aren't taken for code.
+// Computed jumps (BNNN) can't be followed. Also returns the addresses
+// outside of the ROM the program gets to.
+fn reachable_instructions(
+    rom: &[u8],
+    layout: &MemoryLayout,
+    extension: InstructionSet,
+) -> (Vec<(usize, u16)>, Vec<usize>) {
+    let end = layout.start + rom.len();
+    // jumps reach up to 0xFFF even with less memory
+    let mut visited = vec![false; layout.ram_size.max(0x1000) + 4];
+    let mut pending = vec![layout.start];
+    let mut instructions = Vec::new();
+    let mut outside = Vec::new();
+
+    while let Some(address) = pending.pop() {
+        if visited[address] {
+            continue;
+        }
+        visited[address] = true;
+        if address < layout.start || address + 1 >= end {
+            outside.push(address);
+            continue;
+        }
+
+        let offset = address - layout.start;
+        let op_code = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        instructions.push((address, op_code));
+
+        let nnn = (op_code & 0x0FFF) as usize;
+        // XO-CHIP's F000 NNNN is followed by a 16 bit address
+        let length = if op_code == 0xF000 { 4 } else { 2 };
+        let nn = (op_code & 0xFF) as usize;
+        let chip8e = extension == InstructionSet::Chip8E;
+        let chip8x = extension == InstructionSet::Chip8X;
+        match op_code >> 12 {
+            // return, or exit for SUPER-CHIP
+            0x0 if op_code == 0x00EE || op_code == 0x00FD => {}
+            // CHIP-8E stops with 00ED and always skips with 0188
+            0x0 if chip8e && op_code == 0x00ED => {}
+            0x0 if chip8e && op_code == 0x0188 => pending.push(address + 4),
+            // the relative jumps of CHIP-8E, from the next instruction
+            0xB if chip8e && op_code >> 8 == 0xBB => {
+                pending.push((address + 2).wrapping_sub(nn) & 0xFFF)
+            }
+            0xB if chip8e && op_code >> 8 == 0xBF => pending.push((address + 2 + nn) & 0xFFF),
+            // CHIP-8X sets colours with BXYN
+            0xB if chip8x => pending.push(address + 2),
+            // 5XY1 adds for CHIP-8X, 5XY2 and 5XY3 store and load for CHIP-8E
+            0x5 if chip8x && op_code & 0xF == 0x1 => pending.push(address + 2),
+            0x5 if chip8e && op_code & 0xF >= 0x2 => pending.push(address + 2),
+            // skipping VX bytes can't be followed either
+            0xF if chip8e && op_code & 0xFF == 0x1B => {}
+            0x1 => pending.push(nnn),
+            0x2 => pending.extend(&[nnn, address + 2]),
+            0xB => {}
+            // the skips may jump over the next instruction
+            0x3 | 0x4 | 0x5 | 0x9 => pending.extend(&[address + 2, address + 4]),
+            0xE => pending.extend(&[address + 2, address + 4]),
+            _ => pending.push(address + length),
+        }
+    }
+
+    instructions.sort_unstable();
+    outside.sort_unstable();
+    (instructions, outside)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_on(rom: &[u8], platform: Platform) -> io::Result<RomInfo> {
+        check(rom, platform, &platform.quirks(), &platform.memory_layout())
+    }
+
+    fn warnings(rom: &[u8], platform: Platform) -> Vec<String> {
+        check_on(rom, platform).unwrap().warnings
+    }
+
+    #[test]
+    fn rejects_empty_roms() {
+        let err = check_on(&[], Platform::Chip8).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "the ROM is empty");
+    }
+
+    #[test]
+    fn rejects_roms_too_big_for_the_memory() {
+        // 0x200 to the end of the 4K
+        let mut rom = vec![0x12, 0x00];
+        rom.resize(0xE00, 0);
+        assert!(check_on(&rom, Platform::Chip8).is_ok());
+
+        rom.push(0);
+        let err = check_on(&rom, Platform::Chip8).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "the ROM is 3585 bytes, only 3584 fit in memory after 200"
+        );
+    }
+
+    #[test]
+    fn warns_about_odd_sizes() {
+        assert_eq!(
+            warnings(&[0x12, 0x00, 0xFF], Platform::Chip8),
+            vec!["the ROM is 3 bytes, an odd size, so its last byte is half an instruction"]
+        );
+        assert!(warnings(&[0x12, 0x00], Platform::Chip8).is_empty());
+    }
+
+    #[test]
+    fn warns_about_instructions_of_other_platforms() {
+        // 00FF, then a jump to itself
+        let rom = [0x00, 0xFF, 0x12, 0x02];
+        let info = check_on(&rom, Platform::Chip8).unwrap();
+
+        assert_eq!(info.instruction_set, InstructionSet::SuperChip);
+        assert_eq!(
+            info.warnings,
+            vec!["SUPER-CHIP instruction 00FF at 200 isn't supported by the chip8 platform"]
+        );
+    }
+
+    #[test]
+    fn machine_code_needs_the_quirk() {
+        let rom = [0x03, 0x00, 0x12, 0x02];
+
+        assert_eq!(
+            warnings(&rom, Platform::Chip8),
+            vec!["machine code instruction 0300 at 200 isn't supported by the chip8 platform"]
+        );
+        assert!(warnings(&rom, Platform::Vip).is_empty());
+    }
+
+    #[test]
+    fn warns_about_unknown_instructions() {
+        assert_eq!(
+            warnings(&[0x80, 0x08, 0x12, 0x02], Platform::Chip8),
+            vec!["unknown instruction 8008 at 200"]
+        );
+    }
+
+    #[test]
+    fn warns_about_jumps_and_calls_outside_the_rom() {
+        // a call past the end, then a jump before the start
+        let rom = [0x24, 0x00, 0x11, 0x00];
+
+        assert_eq!(
+            warnings(&rom, Platform::Chip8),
+            vec![
+                "jumps to 100, before the start of the program",
+                "runs past the end of the ROM into empty memory at 400",
+            ]
+        );
+    }
+
+    #[test]
+    fn data_after_a_jump_is_not_code() {
+        // the jump skips over 8008, which isn't an instruction
+        let rom = [0x12, 0x04, 0x80, 0x08, 0x12, 0x04];
+        let (instructions, outside) =
+            reachable_instructions(&rom, &MemoryLayout::default(), InstructionSet::Chip8);
+
+        assert_eq!(instructions, vec![(0x200, 0x1204), (0x204, 0x1204)]);
+        assert!(outside.is_empty());
+    }
+
+    #[test]
+    fn skips_reach_both_instructions() {
+        // 3000 may skip the jump out of the ROM
+        let rom = [0x30, 0x00, 0x13, 0x00, 0x12, 0x04];
+        let (instructions, outside) =
+            reachable_instructions(&rom, &MemoryLayout::default(), InstructionSet::Chip8);
+
+        assert_eq!(
+            instructions,
+            vec![(0x200, 0x3000), (0x202, 0x1300), (0x204, 0x1204)]
+        );
+        assert_eq!(outside, vec![0x300]);
+    }
+
+    #[test]
+    fn hashes_the_rom_with_sha1() {
+        // the "abc" test vector of FIPS 180
+        let info = check_on(b"abc", Platform::Chip8);
+
+        assert_eq!(
+            info.unwrap().sha1,
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+}
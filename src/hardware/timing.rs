@@ -0,0 +1,77 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// Machine cycles of the 1802 in a 60Hz frame of the COSMAC VIP, 3668, less
+// the ones taken by the display interrupt and its DMA.
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668 - 1832;
+// fetching and decoding an instruction, before running it
+const FETCH_CYCLES: u64 = 40;
+// taking a skip costs a bit more than not taking it
+const SKIP_CYCLES: u64 = 4;
+
+// How long the instructions take.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    // all of them the same time, set by the speed
+    Uniform,
+    // the time they take in the COSMAC VIP interpreter
+    Vip,
+}
+
+// The machine cycles the VIP interpreter takes for an instruction.
+// `vx` is the value of VX before it runs. These are worked out from the
+// code of the interpreter and only close to the real durations, which
+// also depend on where the operands are in memory.
+pub fn vip_cycles(op_code: u16, vx: u8, skipped: bool) -> u64 {
+    let x = (op_code >> 8 & 0xF) as u64;
+    let n = (op_code & 0xF) as u64;
+    let cycles = match op_code >> 12 {
+        0x0 if op_code == 0x00E0 => 3078,
+        0x0 if op_code == 0x00EE => 10,
+        // machine code is timed by the 1802 running it
+        0x0 => 0,
+        0x1 | 0xA => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10,
+        0x5 | 0x9 => 14,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xB => 22,
+        0xC => 36,
+        // each row is shifted into place one bit at a time
+        0xD => 26 + n * (46 + 8 * (vx & 7) as u64),
+        0xE => 14,
+        _ => match op_code & 0xFF {
+            0x0A => 18,
+            0x1E | 0x29 => 16,
+            // the digits are counted by repeated subtraction
+            0x33 => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u64,
+            0x55 | 0x65 => 14 + 14 * (x + 1),
+            _ => 10,
+        },
+    };
+    FETCH_CYCLES + cycles + if skipped { SKIP_CYCLES } else { 0 }
+}
+
+impl Display for Timing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Timing::Uniform => "uniform",
+            Timing::Vip => "vip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "uniform" => Ok(Timing::Uniform),
+            "vip" | "cosmac-vip" => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing '{}'", s)),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use super::cdp1802::{Bus, Cdp1802};
+use super::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const RAM_SIZE: usize = 4096;
+// the ROM of the monitor and the CHIP-8 interpreter are 512 bytes each
+const IMAGE_SIZE: usize = 512;
+const PROGRAM_START: usize = 0x200;
+
+// Timing of the CDP1861 video chip, in machine cycles of the 1802 and
+// in lines of 14 machine cycles. During the 128 lines of the picture, 8
+// of the 14 cycles of each line are DMA cycles reading the pixels.
+const LINE_CYCLES: u64 = 14;
+const DMA_CYCLES: u64 = 8;
+const FRAME_LINES: usize = 262;
+const INTERRUPT_LINE: usize = 78;
+const DISPLAY_START: usize = 80;
+const DISPLAY_LINES: usize = 128;
+// EF1 is active during the 4 lines before the start and the end of the picture
+const EF1_LINES: usize = 4;
+
+// The firmware the VIP needs, dumped from a real one.
+pub struct VipRoms {
+    // the ROM at 8000, which also has the digits of the CHIP-8 font
+    pub monitor: Vec<u8>,
+    // the CHIP-8 interpreter, loaded at 0000 in RAM
+    pub interpreter: Vec<u8>,
+}
+
+impl VipRoms {
+    pub fn load(monitor: &Path, interpreter: &Path) -> io::Result<Self> {
+        Ok(VipRoms {
+            monitor: read_image(monitor, "monitor ROM")?,
+            interpreter: read_image(interpreter, "interpreter")?,
+        })
+    }
+}
+
+fn read_image(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let image = fs::read(path)?;
+    if image.is_empty() || image.len() > IMAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the {} {} is {} bytes, it should be up to {}",
+                name,
+                path.display(),
+                image.len(),
+                IMAGE_SIZE
+            ),
+        ));
+    }
+    Ok(image)
+}
+
+// What the 1802 of the VIP is wired to.
+struct VipBus {
+    ram: Vec<u8>,
+    monitor: Vec<u8>,
+    // after a reset the monitor is also seen at 0000, until the first
+    // access to the upper half of the addresses
+    monitor_at_zero: bool,
+    keypad: [bool; 16],
+    // the key selected with OUT 2
+    key_latch: usize,
+    // the 1861 is turned on with INP 1 and off with OUT 1
+    display_on: bool,
+    ef1: bool,
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 != 0 {
+            self.monitor_at_zero = false;
+        }
+        if address & 0x8000 != 0 || self.monitor_at_zero {
+            self.monitor[address as usize % self.monitor.len()]
+        } else {
+            self.ram[address as usize % RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 == 0 {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: usize, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.key_latch = (value & 0xF) as usize,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: usize) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    // EF1 comes from the 1861 and EF3 is the selected key being held down
+    fn flag(&self, number: usize) -> bool {
+        match number {
+            0 => self.ef1,
+            2 => self.keypad[self.key_latch],
+            _ => false,
+        }
+    }
+}
+
+// What happened during a frame of the VIP.
+pub struct VipFrame {
+    pub updated_vram: bool,
+    // whether the tone generator was on
+    pub beep: bool,
+    pub instructions: u32,
+}
+
+// A whole COSMAC VIP running its own CHIP-8 interpreter: the 1802, 4K of
+// RAM, the 1861 video chip, the hex keypad and the tone generator driven
+// by Q. Much slower than running the CHIP-8 instructions directly, but
+// its timing and quirks are the real ones.
+pub struct Vip {
+    cpu: Cdp1802,
+    bus: VipBus,
+    interpreter: Vec<u8>,
+    // machine cycles left over by the last instruction of a line, taken
+    // off the next one when negative
+    balance: i64,
+    interrupt_requested: bool,
+    // the picture drawn by DMA during the frame, 8 bytes a line
+    lines: [[u8; 8]; DISPLAY_LINES],
+    pub vram: [u8; CHIP8_WIDTH * CHIP8_HEIGHT],
+}
+
+impl Vip {
+    pub fn new(roms: &VipRoms, program: &[u8]) -> Self {
+        let mut vip = Vip {
+            cpu: Cdp1802::default(),
+            bus: VipBus {
+                ram: vec![0; RAM_SIZE],
+                monitor: roms.monitor.clone(),
+                monitor_at_zero: true,
+                keypad: [false; 16],
+                key_latch: 0,
+                display_on: false,
+                ef1: false,
+            },
+            interpreter: roms.interpreter.clone(),
+            balance: 0,
+            interrupt_requested: false,
+            lines: [[0; 8]; DISPLAY_LINES],
+            vram: [0; CHIP8_WIDTH * CHIP8_HEIGHT],
+        };
+        vip.reset(program);
+        vip
+    }
+
+    // Switches the VIP off and on again with the interpreter and the
+    // program in memory, and lets the monitor start them.
+    pub fn reset(&mut self, program: &[u8]) {
+        self.cpu = Cdp1802::default();
+        let bus = &mut self.bus;
+        bus.ram.iter_mut().for_each(|byte| *byte = 0);
+        bus.ram[..self.interpreter.len()].copy_from_slice(&self.interpreter);
+        let length = program.len().min(RAM_SIZE - PROGRAM_START);
+        bus.ram[PROGRAM_START..PROGRAM_START + length].copy_from_slice(&program[..length]);
+        bus.monitor_at_zero = true;
+        bus.key_latch = 0;
+        bus.display_on = false;
+        self.balance = 0;
+        self.interrupt_requested = false;
+        self.lines = [[0; 8]; DISPLAY_LINES];
+        self.vram = [0; CHIP8_WIDTH * CHIP8_HEIGHT];
+    }
+
+    // Runs the 262 lines of a frame of the 1861, about 1/60 s.
+    pub fn run_frame(&mut self, keypad: [bool; 16]) -> VipFrame {
+        self.bus.keypad = keypad;
+        let mut instructions = 0;
+        let mut beep = self.cpu.q;
+
+        for line in 0..FRAME_LINES {
+            let picture_line = line.wrapping_sub(DISPLAY_START);
+            let in_picture = picture_line < DISPLAY_LINES;
+            self.bus.ef1 = (DISPLAY_START - EF1_LINES..DISPLAY_START).contains(&line)
+                || (DISPLAY_START + DISPLAY_LINES - EF1_LINES..DISPLAY_START + DISPLAY_LINES)
+                    .contains(&line);
+            if line == INTERRUPT_LINE && self.bus.display_on {
+                self.interrupt_requested = true;
+            } else if line == DISPLAY_START {
+                self.interrupt_requested = false;
+            }
+
+            let dma = in_picture && self.bus.display_on;
+            self.balance += if dma {
+                (LINE_CYCLES - DMA_CYCLES) as i64
+            } else {
+                LINE_CYCLES as i64
+            };
+            while self.balance > 0 {
+                if self.interrupt_requested && self.cpu.interrupt() {
+                    self.interrupt_requested = false;
+                    self.balance -= 1;
+                    continue;
+                }
+                self.balance -= self.cpu.step(&mut self.bus) as i64;
+                instructions += 1;
+                beep |= self.cpu.q;
+            }
+
+            if in_picture {
+                for byte in self.lines[picture_line].iter_mut() {
+                    *byte = if dma {
+                        self.cpu.dma_out(&mut self.bus)
+                    } else {
+                        0
+                    };
+                }
+            }
+        }
+
+        VipFrame {
+            updated_vram: self.update_vram(),
+            beep,
+            instructions,
+        }
+    }
+
+    // The interpreter shows each CHIP-8 row on 4 lines, one of them is
+    // enough. Returns whether the screen changed.
+    fn update_vram(&mut self) -> bool {
+        let mut changed = false;
+        let lines_per_row = DISPLAY_LINES / CHIP8_HEIGHT;
+        for (row, pixels) in self.vram.chunks_mut(CHIP8_WIDTH).enumerate() {
+            let line = &self.lines[row * lines_per_row];
+            for (x, pixel) in pixels.iter_mut().enumerate() {
+                let value = line[x / 8] >> (7 - x % 8) & 1;
+                changed |= *pixel != value;
+                *pixel = value;
+            }
+        }
+        changed
+    }
+}
@@ -1,59 +1,91 @@
+use super::tone::{AudioSink, Tone, ToneOptions, SAMPLE_RATE};
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::io;
 
 //https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/audio-squarewave.rs
 pub struct AudioInterface {
-    device: AudioDevice<SquareWave>,
+    // None when muted or when no audio device could be opened
+    device: Option<AudioDevice<Tone>>,
+    beeping: bool,
 }
 
 impl AudioInterface {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        let audio_subsystem = sdl_context.audio().unwrap();
-
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1), // mono
-            samples: None,     // default sample size
-        };
-
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-                // Show obtained AudioSpec
-                println!("{:?}", spec);
-
-                // initialize the audio callback
-                SquareWave {
-                    phase_inc: 240.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
+    pub fn new(sdl_context: &sdl2::Sdl, options: &ToneOptions, mute: bool) -> Self {
+        let device = if mute {
+            None
+        } else {
+            match open_device(sdl_context, options) {
+                Ok(device) => Some(device),
+                Err(err) => {
+                    eprintln!(
+                        "Unable to open the audio device, running silently:\n{}",
+                        err
+                    );
+                    None
                 }
-            })
-            .unwrap();
+            }
+        };
 
-        AudioInterface { device }
+        AudioInterface {
+            device,
+            beeping: false,
+        }
     }
 
-    pub fn beep(&self) {
-        self.device.resume();
+    pub fn beep(&mut self) {
+        self.set_gate(true);
     }
-    pub fn no_beep(&self) {
-        self.device.pause();
+    pub fn no_beep(&mut self) {
+        self.set_gate(false);
     }
+
+    // The device keeps playing, only the envelope of the tone is switched.
+    fn set_gate(&mut self, beep: bool) {
+        if beep == self.beeping {
+            return;
+        }
+        self.beeping = beep;
+        if let Some(device) = &mut self.device {
+            device.lock().gate = beep;
+        }
+    }
+}
+
+fn open_device(
+    sdl_context: &sdl2::Sdl,
+    options: &ToneOptions,
+) -> Result<AudioDevice<Tone>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+
+    let desired_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1), // mono
+        samples: None,     // default sample size
+    };
+
+    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+        // initialize the audio callback
+        Tone::new(spec.freq, options)
+    })?;
+    device.resume();
+    Ok(device)
 }
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+impl AudioSink for AudioInterface {
+    fn play_frame(&mut self, beep: bool) -> io::Result<()> {
+        if beep {
+            self.beep();
+        } else {
+            self.no_beep();
+        }
+        Ok(())
+    }
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Tone {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.fill(out);
     }
 }
@@ -1,69 +1,331 @@
 use sdl2;
-use sdl2::pixels;
+use sdl2::pixels::{self, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use std::str::FromStr;
 
+use super::effects::{EffectChain, EffectPipeline, FrameBuffer};
+use super::menu::Menu;
+use super::osd::Osd;
+use super::palette::{self, Palette};
+use crate::hardware::color::ColorZones;
 use crate::hardware::{CHIP8_HEIGHT, CHIP8_WIDTH};
-use rand::Rng;
 
-const SCALE_FACTOR: u32 = 10;
-const NOISE_FACTOR: u32 = 4;
-const SCREEN_WIDTH: u32 = (CHIP8_WIDTH as u32) * SCALE_FACTOR;
-const SCREEN_HEIGHT: u32 = (CHIP8_HEIGHT as u32) * SCALE_FACTOR;
+const FRAME_SIZE: usize = CHIP8_WIDTH * CHIP8_HEIGHT;
+const WINDOW_TITLE: &str = "Chip-8 Emulator";
+
+// How pixels that have been turned off linger on the screen, to
+// hide the flickering of sprites being erased and drawn again.
+#[derive(Clone, Copy, Debug)]
+pub enum Persistence {
+    Off,
+    // pixels fade out over the given number of frames
+    Phosphor(u32),
+    // pixels lit in either of the last two frames are shown
+    Blend,
+}
+
+// How the image is fit into a window of any size,
+// the rest of the window is filled with black bars.
+#[derive(Clone, Copy, Debug)]
+pub enum Scaling {
+    // the largest whole multiple of the CHIP-8 resolution that fits
+    Integer,
+    // as large as possible, keeping the aspect ratio
+    Fit,
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!("unknown scaling '{}'", s)),
+        }
+    }
+}
+
+pub struct DisplayOptions {
+    // initial size of a CHIP-8 pixel in the window, also the
+    // resolution the display effects are applied at
+    pub scale: u32,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub palette: Palette,
+    pub persistence: Persistence,
+    pub effects: EffectChain,
+    pub seed: u64,
+    // what the on-screen display starts with
+    pub osd: bool,
+    pub show_stats: bool,
+    pub show_keys: bool,
+}
 
 pub struct DisplayInterface {
+    texture: Texture,
     canvas: Canvas<Window>,
+    buffer: FrameBuffer,
+    // size of a CHIP-8 pixel in the buffer
+    buffer_scale: usize,
+    scaling: Scaling,
+    effects: EffectPipeline,
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    persistence: Persistence,
+    frame: [u8; FRAME_SIZE],
+    // last colour each pixel was lit with and how bright it still is
+    glow: [u8; FRAME_SIZE],
+    brightness: [f32; FRAME_SIZE],
+    // the colours of CHIP-8X, shown instead of the palette
+    colors: Option<ColorZones>,
+    dirty: bool,
+    status: Option<String>,
+    osd: Osd,
 }
 
 impl DisplayInterface {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(sdl_context: &sdl2::Sdl, options: DisplayOptions) -> Self {
         let video_subsys = sdl_context.video().unwrap();
-        let window = video_subsys
-            .window("Chip-8 Emulator", SCREEN_WIDTH, SCREEN_HEIGHT)
-            .position_centered()
-            .opengl()
-            .build()
-            .unwrap();
+        let scale = options.scale.max(1);
+        let width = CHIP8_WIDTH as u32 * scale;
+        let height = CHIP8_HEIGHT as u32 * scale;
+        let mut window_builder = video_subsys.window(WINDOW_TITLE, width, height);
+        window_builder.position_centered().resizable().opengl();
+        if options.fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+        let window = window_builder.build().unwrap();
 
+        // keep the pixels sharp when the texture is stretched
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
         let mut canvas = window.into_canvas().build().unwrap();
 
         canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
 
-        DisplayInterface { canvas }
+        // Without effects the image is uploaded at the CHIP-8 resolution
+        // and stretched by SDL, effects need a higher resolution to work on.
+        let buffer_scale = if options.effects.is_empty() { 1 } else { scale };
+        let buffer = FrameBuffer::new(
+            CHIP8_WIDTH * buffer_scale as usize,
+            CHIP8_HEIGHT * buffer_scale as usize,
+        );
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                buffer.width as u32,
+                buffer.height as u32,
+            )
+            .unwrap();
+
+        // the configured palette comes first, followed by the presets
+        let mut palettes = Palette::presets();
+        palettes.retain(|palette| palette.name != options.palette.name);
+        palettes.insert(0, options.palette);
+
+        DisplayInterface {
+            texture,
+            canvas,
+            buffer,
+            buffer_scale: buffer_scale as usize,
+            scaling: options.scaling,
+            effects: EffectPipeline::new(options.effects, options.seed),
+            palettes,
+            palette_index: 0,
+            persistence: options.persistence,
+            frame: [0u8; FRAME_SIZE],
+            glow: [0u8; FRAME_SIZE],
+            brightness: [0.; FRAME_SIZE],
+            colors: None,
+            dirty: true,
+            status: None,
+            osd: Osd::new(options.osd, options.show_stats, options.show_keys),
+        }
+    }
+
+    // Shows the state of the emulator, e.g. "Paused", in the title bar.
+    pub fn set_status(&mut self, status: Option<String>) {
+        if status == self.status {
+            return;
+        }
+        let title = match &status {
+            Some(status) => format!("{} - {}", WINDOW_TITLE, status),
+            None => String::from(WINDOW_TITLE),
+        };
+        let _ = self.canvas.window_mut().set_title(&title);
+        self.dirty |= self.osd.set_status(status.clone());
+        self.status = status;
+    }
+
+    pub fn show_message(&mut self, text: String) {
+        self.osd.message(text);
+        self.dirty = true;
+    }
+
+    // Feeds the on-screen statistics and key overlay, once per frame.
+    pub fn update_osd(&mut self, cycles: u64, keypad: [bool; 16]) {
+        self.dirty |= self.osd.set_keypad(keypad);
+        self.dirty |= self.osd.tick(cycles);
+    }
+
+    pub fn toggle_osd(&mut self) {
+        self.osd.enabled = !self.osd.enabled;
+        self.dirty = true;
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.osd.show_stats = !self.osd.show_stats;
+        self.osd.enabled = true;
+        self.dirty = true;
     }
 
-    pub fn draw(&mut self, pixels: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT]) {
+    pub fn toggle_keys(&mut self) {
+        self.osd.show_keys = !self.osd.show_keys;
+        self.osd.enabled = true;
+        self.dirty = true;
+    }
+
+    // Whether the screen changes over time even if the VRAM doesn't,
+    // in which case it has to be drawn on every frame.
+    pub fn is_animated(&self) -> bool {
+        !matches!(self.persistence, Persistence::Off)
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(err) = window.set_fullscreen(fullscreen) {
+            eprintln!("Unable to toggle fullscreen: {}", err);
+        }
+        self.dirty = true;
+    }
+
+    // Draws the current image again, e.g. after the window was resized.
+    pub fn refresh(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
+    }
+
+    pub fn next_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        let name = self.palettes[self.palette_index].name.clone();
+        self.show_message(format!("Palette: {}", name));
+    }
+
+    pub fn draw(&mut self, pixels: &[u8; FRAME_SIZE], colors: Option<&ColorZones>) {
         for (index, &pixel) in pixels.iter().enumerate() {
-            let x = index % CHIP8_WIDTH;
-            let y = index / CHIP8_WIDTH;
-            let x = (x as u32) * SCALE_FACTOR;
-            let y = (y as u32) * SCALE_FACTOR;
-            let mut rng = rand::thread_rng();
-            for x_off in 0..SCALE_FACTOR / NOISE_FACTOR {
-                for y_off in 0..SCALE_FACTOR / NOISE_FACTOR {
-                    let rand: u8 = rng.gen_range(190, 240);
-
-                    self.canvas.set_draw_color(color(pixel * rand));
-                    self.canvas.fill_rect(Rect::new(
-                        (x + x_off * NOISE_FACTOR) as i32,
-                        (y + y_off * NOISE_FACTOR) as i32,
-                        NOISE_FACTOR,
-                        NOISE_FACTOR,
-                    ));
+            let previous = self.frame[index];
+            let (glow, brightness) = match self.persistence {
+                Persistence::Off => (pixel, 1.),
+                Persistence::Phosphor(_) if pixel > 0 => (pixel, 1.),
+                Persistence::Phosphor(frames) => (
+                    self.glow[index],
+                    (self.brightness[index] - 1. / frames.max(1) as f32).max(0.),
+                ),
+                Persistence::Blend => (if pixel > 0 { pixel } else { previous }, 1.),
+            };
+            self.glow[index] = glow;
+            self.brightness[index] = brightness;
+        }
+        self.frame = *pixels;
+        self.colors = colors.copied();
+        self.dirty = true;
+    }
+
+    // Shows the ROM menu instead of the game, with the OSD over it.
+    pub fn show_menu(&mut self, menu: &mut Menu) {
+        menu.render(&mut self.canvas, &self.palettes[self.palette_index]);
+        self.osd.render(&mut self.canvas);
+        self.canvas.present();
+        // the game has to be drawn again once the menu is closed
+        self.dirty = true;
+    }
+
+    // Shows the changes since the last frame, if any.
+    // Must be called once per frame.
+    pub fn present(&mut self) {
+        if self.dirty {
+            self.render();
+            self.dirty = false;
+        }
+    }
+
+    // Draws the CHIP-8 pixels into the frame buffer, applies the
+    // effects on top and shows the result with the OSD over it.
+    fn render(&mut self) {
+        let palette = &self.palettes[self.palette_index];
+        let background = match &self.colors {
+            Some(colors) => palette::rgb(colors.background_color()),
+            None => palette.color(0),
+        };
+        let scale = self.buffer_scale;
+        let pitch = self.buffer.pitch();
+        for (index, &pixel) in self.glow.iter().enumerate() {
+            let (column, row) = (index % CHIP8_WIDTH, index / CHIP8_WIDTH);
+            let foreground = match &self.colors {
+                Some(colors) if pixel > 0 => palette::rgb(colors.foreground_color(column, row)),
+                Some(_) => background,
+                None => palette.color(pixel),
+            };
+            let color = fade(background, foreground, self.brightness[index]);
+            let x = column * scale;
+            let y = row * scale;
+            for row in y..y + scale {
+                let offset = row * pitch + x * 3;
+                for rgb in self.buffer.pixels[offset..offset + scale * 3].chunks_mut(3) {
+                    rgb.copy_from_slice(&[color.r, color.g, color.b]);
                 }
             }
         }
+
+        self.effects.apply(&mut self.buffer, scale);
+
+        let _ = self.texture.update(None, &self.buffer.pixels, pitch);
+        self.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        let target = self.target_rect();
+        let _ = self.canvas.copy(&self.texture, None, target);
+        self.osd.render(&mut self.canvas);
         self.canvas.present();
     }
-}
 
-fn color(value: u8) -> pixels::Color {
-    if value == 0 {
-        pixels::Color::RGB(0, 0, 0)
-    } else {
-        pixels::Color::RGB(0, value, 0)
+    // Where the image goes in the window, centred and scaled
+    // according to the scaling mode.
+    fn target_rect(&self) -> Option<Rect> {
+        let (window_width, window_height) = self.canvas.output_size().ok()?;
+        let (width, height) = (CHIP8_WIDTH as f32, CHIP8_HEIGHT as f32);
+        let factor = (window_width as f32 / width).min(window_height as f32 / height);
+        let factor = match self.scaling {
+            Scaling::Integer => factor.floor().max(1.),
+            Scaling::Fit => factor,
+        };
+        let (target_width, target_height) = ((width * factor) as u32, (height * factor) as u32);
+        Some(Rect::new(
+            (window_width as i32 - target_width as i32) / 2,
+            (window_height as i32 - target_height as i32) / 2,
+            target_width,
+            target_height,
+        ))
     }
 }
+
+// Mixes the pixel colour into the background by the given amount.
+fn fade(background: pixels::Color, color: pixels::Color, amount: f32) -> pixels::Color {
+    let mix = |b: u8, c: u8| (b as f32 + (c as f32 - b as f32) * amount) as u8;
+    pixels::Color::RGB(
+        mix(background.r, color.r),
+        mix(background.g, color.g),
+        mix(background.b, color.b),
+    )
+}
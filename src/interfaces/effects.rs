@@ -0,0 +1,199 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+
+const NOISE_FACTOR: usize = 4;
+
+// An RGB24 image the display effects are applied to before
+// it is handed over to SDL.
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0u8; width * height * 3],
+        }
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.width * 3
+    }
+
+    fn darken(&mut self, x: usize, y: usize, amount: f32) {
+        let offset = (y * self.width + x) * 3;
+        for c in &mut self.pixels[offset..offset + 3] {
+            *c = (*c as f32 * (1. - amount)) as u8;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Effect {
+    // random dimming of 4x4 blocks, like the noise of an old screen
+    Noise,
+    // every other line of the window is darker
+    Scanlines,
+    // thin dark lines between the CHIP-8 pixels
+    Grid,
+    // lit pixels glow onto their neighbours
+    Bloom,
+}
+
+impl Effect {
+    fn default_intensity(self) -> f32 {
+        match self {
+            Effect::Noise => 0.2,
+            Effect::Scanlines => 0.3,
+            Effect::Grid => 0.5,
+            Effect::Bloom => 0.4,
+        }
+    }
+}
+
+// The chain of effects applied to every frame, in order.
+// It is written as a comma separated list of effects with an optional
+// intensity between 0 and 1, e.g. "scanlines=0.5,bloom".
+#[derive(Clone, Debug, Default)]
+pub struct EffectChain(Vec<(Effect, f32)>);
+
+impl EffectChain {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for EffectChain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chain = Vec::new();
+        for setting in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let effect = match parts.next().unwrap_or_default() {
+                "none" => continue,
+                "noise" => Effect::Noise,
+                "scanlines" => Effect::Scanlines,
+                "grid" => Effect::Grid,
+                "bloom" => Effect::Bloom,
+                name => return Err(format!("unknown effect '{}'", name)),
+            };
+            let intensity = match parts.next() {
+                Some(value) => value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|i| (0. ..=1.).contains(i))
+                    .ok_or(format!("invalid intensity '{}' for {:?}", value, effect))?,
+                None => effect.default_intensity(),
+            };
+            chain.push((effect, intensity));
+        }
+        Ok(EffectChain(chain))
+    }
+}
+
+pub struct EffectPipeline {
+    chain: EffectChain,
+    rng: StdRng,
+}
+
+impl EffectPipeline {
+    pub fn new(chain: EffectChain, seed: u64) -> Self {
+        EffectPipeline {
+            chain,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // `scale` is the size in the buffer of a single CHIP-8 pixel.
+    pub fn apply(&mut self, buffer: &mut FrameBuffer, scale: usize) {
+        for &(effect, intensity) in &self.chain.0 {
+            match effect {
+                Effect::Noise => noise(buffer, intensity, &mut self.rng),
+                Effect::Scanlines => scanlines(buffer, intensity),
+                Effect::Grid => grid(buffer, scale, intensity),
+                Effect::Bloom => bloom(buffer, scale, intensity),
+            }
+        }
+    }
+}
+
+fn noise(buffer: &mut FrameBuffer, intensity: f32, rng: &mut StdRng) {
+    for block_y in (0..buffer.height).step_by(NOISE_FACTOR) {
+        for block_x in (0..buffer.width).step_by(NOISE_FACTOR) {
+            let amount = rng.gen_range(0., intensity.max(f32::EPSILON));
+            for y in block_y..(block_y + NOISE_FACTOR).min(buffer.height) {
+                for x in block_x..(block_x + NOISE_FACTOR).min(buffer.width) {
+                    buffer.darken(x, y, amount);
+                }
+            }
+        }
+    }
+}
+
+fn scanlines(buffer: &mut FrameBuffer, intensity: f32) {
+    for y in (1..buffer.height).step_by(2) {
+        for x in 0..buffer.width {
+            buffer.darken(x, y, intensity);
+        }
+    }
+}
+
+fn grid(buffer: &mut FrameBuffer, scale: usize, intensity: f32) {
+    if scale < 2 {
+        return;
+    }
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                buffer.darken(x, y, intensity);
+            }
+        }
+    }
+}
+
+// Adds a box blurred copy of the image on top of itself.
+fn bloom(buffer: &mut FrameBuffer, scale: usize, intensity: f32) {
+    let radius = scale.max(1);
+    let horizontal = box_blur(&buffer.pixels, buffer.height, buffer.width, radius, 1);
+    let blurred = box_blur(
+        &horizontal,
+        buffer.width,
+        buffer.height,
+        radius,
+        buffer.width,
+    );
+    for (pixel, glow) in buffer.pixels.iter_mut().zip(blurred) {
+        *pixel = (*pixel as f32 + glow as f32 * intensity).min(255.) as u8;
+    }
+}
+
+// Blurs `lines` lines of `length` pixels each, `step` being the distance
+// in pixels between two consecutive pixels of a line. Lines are
+// consecutive when walking along rows and interleaved along columns.
+fn box_blur(pixels: &[u8], lines: usize, length: usize, radius: usize, step: usize) -> Vec<u8> {
+    let line_step = if step == 1 { length } else { 1 };
+    let window = (2 * radius + 1) as u32;
+    let mut blurred = vec![0u8; pixels.len()];
+    for line in 0..lines {
+        for channel in 0..3 {
+            let at = |i: usize| (line * line_step + i * step) * 3 + channel;
+            let mut sum: u32 = (0..radius.min(length)).map(|i| pixels[at(i)] as u32).sum();
+            for i in 0..length {
+                if i + radius < length {
+                    sum += pixels[at(i + radius)] as u32;
+                }
+                if i > radius {
+                    sum -= pixels[at(i - radius - 1)] as u32;
+                }
+                blurred[at(i)] = (sum / window) as u8;
+            }
+        }
+    }
+    blurred
+}
@@ -1,13 +1,52 @@
+use sdl2::controller::{Axis, Button, GameController};
 use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
+use sdl2::{EventPump, GameControllerSubsystem};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const DEFAULT_AXIS_THRESHOLD: u16 = 16000;
+// the largest value an axis reports
+const MAX_AXIS_THRESHOLD: u16 = 32767;
 
 pub struct InputInterface {
-    //event_pump: &'a EventPump
+    controller_subsystem: GameControllerSubsystem,
+    controllers: HashMap<u32, GameController>,
+    mapping: GamepadMapping,
 }
 
 impl InputInterface {
-    pub fn new() -> InputInterface {
-        InputInterface {}
+    pub fn new(sdl_context: &sdl2::Sdl, mapping: GamepadMapping) -> InputInterface {
+        let controller_subsystem = sdl_context.game_controller().unwrap();
+
+        InputInterface {
+            controller_subsystem,
+            controllers: HashMap::new(),
+            mapping,
+        }
+    }
+
+    // Changes the mapping, e.g. when another game is started.
+    pub fn set_mapping(&mut self, mapping: GamepadMapping) {
+        self.mapping = mapping;
+    }
+
+    // SDL reports every controller already plugged in at startup with
+    // a device added event too, so this is the only place they get opened.
+    pub fn add_controller(&mut self, joystick_index: u32) {
+        if !self.controller_subsystem.is_game_controller(joystick_index) {
+            return;
+        }
+        match self.controller_subsystem.open(joystick_index) {
+            Ok(controller) => {
+                self.controllers
+                    .insert(controller.instance_id(), controller);
+            }
+            Err(err) => eprintln!("Unable to open game controller: {}", err),
+        }
+    }
+
+    pub fn remove_controller(&mut self, instance_id: u32) {
+        self.controllers.remove(&instance_id);
     }
 
     pub fn poll(&self, event_pump: &EventPump) -> [bool; 16] {
@@ -45,6 +84,167 @@ impl InputInterface {
             }
         }
 
+        for controller in self.controllers.values() {
+            self.mapping.apply(controller, &mut chip8_keys);
+        }
+
         chip8_keys
     }
 }
+
+#[derive(Clone, Copy, Debug)]
+pub enum AxisDirection {
+    Negative,
+    Positive,
+}
+
+/// Maps game controller buttons and analog axes onto the 16-key keypad.
+///
+/// The textual form is a comma separated list of `control=key` pairs,
+/// where `control` is an SDL controller button name (`a`, `dpup`, ...)
+/// or an axis name followed by `-` or `+` (`leftx-`, `lefty+`, ...),
+/// and `key` is the hex digit of the keypad key.
+#[derive(Clone, Debug)]
+pub struct GamepadMapping {
+    pub buttons: Vec<(Button, usize)>,
+    pub axes: Vec<(Axis, AxisDirection, usize)>,
+    pub axis_threshold: u16,
+}
+
+impl GamepadMapping {
+    fn apply(&self, controller: &GameController, chip8_keys: &mut [bool; 16]) {
+        for &(button, key) in &self.buttons {
+            if controller.button(button) {
+                chip8_keys[key] = true;
+            }
+        }
+
+        for &(axis, direction, key) in &self.axes {
+            let value = controller.axis(axis) as i32;
+            let threshold = self.axis_threshold as i32;
+            let pressed = match direction {
+                AxisDirection::Negative => value <= -threshold,
+                AxisDirection::Positive => value >= threshold,
+            };
+            if pressed {
+                chip8_keys[key] = true;
+            }
+        }
+    }
+}
+
+// Parses how far a stick has to be pushed, a stick at rest must not
+// count as pushed, so 0 isn't allowed.
+pub fn parse_axis_threshold(s: &str) -> Result<u16, String> {
+    s.parse()
+        .ok()
+        .filter(|threshold| (1..=MAX_AXIS_THRESHOLD).contains(threshold))
+        .ok_or(format!(
+            "invalid axis threshold '{}', expected 1-{}",
+            s, MAX_AXIS_THRESHOLD
+        ))
+}
+
+impl Default for GamepadMapping {
+    // The d-pad and the left stick follow the usual 2/4/6/8 CHIP-8
+    // direction keys, 5 being the most common action key.
+    fn default() -> Self {
+        "dpup=2,dpdown=8,dpleft=4,dpright=6,\
+         lefty-=2,lefty+=8,leftx-=4,leftx+=6,\
+         a=5,b=6,x=4,y=8,back=0,start=f"
+            .parse()
+            .unwrap()
+    }
+}
+
+impl FromStr for GamepadMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = GamepadMapping {
+            buttons: Vec::new(),
+            axes: Vec::new(),
+            axis_threshold: DEFAULT_AXIS_THRESHOLD,
+        };
+
+        for binding in s.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+            let mut parts = binding.splitn(2, '=');
+            let control = parts.next().unwrap_or_default();
+            let key = parts
+                .next()
+                .and_then(|k| usize::from_str_radix(k, 16).ok())
+                .filter(|&k| k < 16)
+                .ok_or(format!("invalid keypad key in '{}'", binding))?;
+
+            if let Some(axis) = control.strip_suffix('-').and_then(Axis::from_string) {
+                mapping.axes.push((axis, AxisDirection::Negative, key));
+            } else if let Some(axis) = control.strip_suffix('+').and_then(Axis::from_string) {
+                mapping.axes.push((axis, AxisDirection::Positive, key));
+            } else if let Some(button) = Button::from_string(control) {
+                mapping.buttons.push((button, key));
+            } else {
+                return Err(format!("unknown controller input '{}'", control));
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_buttons_and_axes() {
+        let mapping: GamepadMapping = "a=5, leftx-=4,lefty+=8,start=f".parse().unwrap();
+
+        assert_eq!(
+            mapping.buttons,
+            vec![(Button::A, 0x5), (Button::Start, 0xf)]
+        );
+        assert_eq!(mapping.axes.len(), 2);
+        assert!(matches!(
+            mapping.axes[0],
+            (Axis::LeftX, AxisDirection::Negative, 0x4)
+        ));
+        assert!(matches!(
+            mapping.axes[1],
+            (Axis::LeftY, AxisDirection::Positive, 0x8)
+        ));
+        assert_eq!(mapping.axis_threshold, DEFAULT_AXIS_THRESHOLD);
+    }
+
+    #[test]
+    fn default_mapping_parses() {
+        let mapping = GamepadMapping::default();
+
+        assert_eq!(mapping.buttons.len(), 10);
+        assert_eq!(mapping.axes.len(), 4);
+    }
+
+    #[test]
+    fn rejects_unknown_controller_input() {
+        let err = "a=5,trigger=6".parse::<GamepadMapping>().unwrap_err();
+
+        assert_eq!(err, "unknown controller input 'trigger'");
+    }
+
+    #[test]
+    fn rejects_key_out_of_range() {
+        for binding in &["a=10", "a=g", "a=", "a"] {
+            let err = binding.parse::<GamepadMapping>().unwrap_err();
+
+            assert_eq!(err, format!("invalid keypad key in '{}'", binding));
+        }
+    }
+
+    #[test]
+    fn parses_axis_threshold() {
+        assert_eq!(parse_axis_threshold("1"), Ok(1));
+        assert_eq!(parse_axis_threshold("32767"), Ok(MAX_AXIS_THRESHOLD));
+        assert!(parse_axis_threshold("0").is_err());
+        assert!(parse_axis_threshold("32768").is_err());
+        assert!(parse_axis_threshold("-32768").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use super::osd::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::palette::Palette;
+use crate::library::{self, RomEntry};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::path::{Path, PathBuf};
+
+// lines of the description shown below the list
+const DESCRIPTION_LINES: usize = 3;
+// items skipped by page up and down
+const PAGE: usize = 10;
+
+enum Row {
+    Heading(String),
+    Rom(usize),
+}
+
+// The list of ROMs to pick a game from, the recently played ones first.
+pub struct Menu {
+    roms: Vec<RomEntry>,
+    rows: Vec<Row>,
+    selected: usize,
+    // first row shown, to keep the selection on screen
+    scroll: usize,
+    empty_message: String,
+}
+
+impl Menu {
+    pub fn new(folder: &Path, recent: &[PathBuf]) -> Self {
+        let mut roms: Vec<RomEntry> = recent.iter().map(|path| RomEntry::new(path)).collect();
+        let mut rows = Vec::new();
+        if !roms.is_empty() {
+            rows.push(Row::Heading(String::from("Recent")));
+            rows.extend((0..roms.len()).map(Row::Rom));
+        }
+
+        let mut empty_message = format!("No ROMs in {}", folder.display());
+        match library::scan(folder) {
+            Ok(found) if !found.is_empty() => {
+                rows.push(Row::Heading(folder.display().to_string()));
+                rows.extend((roms.len()..roms.len() + found.len()).map(Row::Rom));
+                roms.extend(found);
+            }
+            Ok(_) => {}
+            Err(err) => empty_message = format!("Unable to read {}: {}", folder.display(), err),
+        }
+
+        Menu {
+            roms,
+            rows,
+            selected: 0,
+            scroll: 0,
+            empty_message,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&RomEntry> {
+        self.roms.get(self.selected)
+    }
+
+    pub fn up(&mut self, steps: usize) {
+        self.selected = self.selected.saturating_sub(steps);
+    }
+
+    pub fn down(&mut self, steps: usize) {
+        self.selected = (self.selected + steps).min(self.roms.len().saturating_sub(1));
+    }
+
+    pub fn page_up(&mut self) {
+        self.up(PAGE);
+    }
+
+    pub fn page_down(&mut self) {
+        self.down(PAGE);
+    }
+
+    // Draws the menu over the whole window with the palette colours.
+    pub fn render(&mut self, canvas: &mut Canvas<Window>, palette: &Palette) {
+        let (width, height) = match canvas.output_size() {
+            Ok((width, height)) => (width as i32, height as i32),
+            Err(_) => return,
+        };
+        let (background, foreground) = (palette.color(0), palette.color(1));
+        // size of a font pixel
+        let size = (height / 120).max(1);
+        let line_height = (GLYPH_HEIGHT + 3) * size;
+        let columns = ((width / size - 2) / (GLYPH_WIDTH + 1)).max(1) as usize;
+        let margin = size * 2;
+
+        canvas.set_draw_color(background);
+        canvas.clear();
+
+        let mut y = margin;
+        let title = "Chip-8 Emulator - Enter to play, Esc to quit";
+        osd::draw_string(
+            canvas,
+            &truncate(title, columns),
+            margin,
+            y,
+            size,
+            foreground,
+        );
+        y += line_height * 2;
+
+        if self.roms.is_empty() {
+            let mut lines = wrap(&self.empty_message, columns);
+            lines.push(String::new());
+            lines.extend(wrap("Drop a ROM file on the window to play it", columns));
+            for line in lines {
+                osd::draw_string(canvas, &line, margin, y, size, foreground);
+                y += line_height;
+            }
+            return;
+        }
+
+        // keep the selected ROM in the part of the list that fits
+        let description_top = height - margin - line_height * DESCRIPTION_LINES as i32;
+        let visible_rows = (((description_top - line_height - y) / line_height).max(1)) as usize;
+        let selected_row = self
+            .rows
+            .iter()
+            .position(|row| matches!(row, Row::Rom(index) if *index == self.selected))
+            .unwrap_or(0);
+        if selected_row < self.scroll {
+            self.scroll = selected_row;
+        } else if selected_row >= self.scroll + visible_rows {
+            self.scroll = selected_row + 1 - visible_rows;
+        }
+
+        for row in self.rows.iter().skip(self.scroll).take(visible_rows) {
+            match row {
+                Row::Heading(heading) => {
+                    let heading = format!("- {} -", heading);
+                    osd::draw_string(
+                        canvas,
+                        &truncate(&heading, columns),
+                        margin,
+                        y,
+                        size,
+                        foreground,
+                    );
+                }
+                Row::Rom(index) => {
+                    let title = truncate(&format!("  {}", self.roms[*index].title), columns);
+                    let color = if *index == self.selected {
+                        canvas.set_draw_color(foreground);
+                        let _ = canvas.fill_rect(Rect::new(
+                            0,
+                            y - size,
+                            width as u32,
+                            line_height as u32,
+                        ));
+                        background
+                    } else {
+                        foreground
+                    };
+                    osd::draw_string(canvas, &title, margin, y, size, color);
+                }
+            }
+            y += line_height;
+        }
+
+        if let Some(rom) = self.selected() {
+            let mut y = description_top;
+            for line in wrap(&rom.description, columns)
+                .iter()
+                .take(DESCRIPTION_LINES)
+            {
+                osd::draw_string(canvas, line, margin, y, size, foreground);
+                y += line_height;
+            }
+        }
+    }
+}
+
+fn truncate(text: &str, columns: usize) -> String {
+    text.chars().take(columns).collect()
+}
+
+// Splits the text into lines of at most `columns` characters, breaking
+// between words where possible.
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let length = line.chars().count();
+        if length > 0 && length + 1 + word.chars().count() > columns {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+        while line.chars().count() > columns {
+            let rest: String = line.chars().skip(columns).collect();
+            lines.push(truncate(&line, columns));
+            line = rest;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
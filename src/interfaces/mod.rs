@@ -1,13 +1,50 @@
 pub mod audio_interface;
 pub mod display_interface;
+pub mod effects;
 pub mod input_interface;
+pub mod menu;
+pub mod osd;
+pub mod palette;
+pub mod recorder;
+pub mod screenshot;
+pub mod tone;
 
 use audio_interface::AudioInterface;
-use display_interface::DisplayInterface;
-use input_interface::InputInterface;
+use display_interface::{DisplayInterface, DisplayOptions};
+use input_interface::{GamepadMapping, InputInterface};
+use tone::ToneOptions;
 
-use sdl2::event::Event;
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
 use sdl2::EventPump;
+use std::path::PathBuf;
+
+// Requests from the user the emulator has to act upon.
+pub enum Command {
+    Quit,
+    Screenshot,
+    ToggleRecording,
+    TogglePause,
+    // emulate a single frame while paused
+    FrameAdvance,
+    // restart the game
+    SoftReset,
+    // reload the ROM file and restart with the initial speed
+    HardReset,
+    SpeedUp,
+    SpeedDown,
+    // leave the game for the menu, or quit from the menu
+    Back,
+    // menu navigation
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Select,
+    // a file dropped on the window
+    Open(PathBuf),
+}
 
 pub struct InterfaceManager {
     pub(crate) event_pump: EventPump,
@@ -17,13 +54,18 @@ pub struct InterfaceManager {
 }
 
 impl InterfaceManager {
-    pub fn new() -> InterfaceManager {
+    pub fn new(
+        display_options: DisplayOptions,
+        gamepad_mapping: GamepadMapping,
+        tone_options: &ToneOptions,
+        mute: bool,
+    ) -> InterfaceManager {
         let sdl_context = sdl2::init().unwrap();
         let event_pump = sdl_context.event_pump().unwrap();
 
-        let video_interface = DisplayInterface::new(&sdl_context);
-        let audio_interface = AudioInterface::new(&sdl_context);
-        let input_interface = InputInterface::new();
+        let video_interface = DisplayInterface::new(&sdl_context, display_options);
+        let audio_interface = AudioInterface::new(&sdl_context, tone_options, mute);
+        let input_interface = InputInterface::new(&sdl_context, gamepad_mapping);
         InterfaceManager {
             event_pump,
             video_interface,
@@ -32,15 +74,192 @@ impl InterfaceManager {
         }
     }
 
-    pub fn run(&mut self) -> Option<Event> {
+    pub fn run(&mut self) -> Option<Command> {
         for event in self.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => {
-                    return Some(event);
+                    return Some(Command::Quit);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::Guide,
+                    ..
+                } => {
+                    return Some(Command::Back);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Up),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::DPadUp,
+                    ..
+                } => {
+                    return Some(Command::Up);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Down),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::DPadDown,
+                    ..
+                } => {
+                    return Some(Command::Down);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::LeftShoulder,
+                    ..
+                } => {
+                    return Some(Command::PageUp);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::RightShoulder,
+                    ..
+                } => {
+                    return Some(Command::PageDown);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpEnter),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::A, ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::Start,
+                    ..
+                } => {
+                    return Some(Command::Select);
+                }
+                Event::DropFile { filename, .. } => {
+                    return Some(Command::Open(PathBuf::from(filename)));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    return Some(Command::Screenshot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    return Some(Command::ToggleRecording);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    return Some(Command::TogglePause);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => {
+                    return Some(Command::FrameAdvance);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    keymod,
+                    ..
+                } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        return Some(Command::HardReset);
+                    }
+                    return Some(Command::SoftReset);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpPlus),
+                    ..
+                } => {
+                    return Some(Command::SpeedUp);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpMinus),
+                    ..
+                } => {
+                    return Some(Command::SpeedDown);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    self.video_interface.toggle_osd();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    self.video_interface.toggle_stats();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    self.video_interface.toggle_keys();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    self.video_interface.next_palette();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    self.video_interface.toggle_fullscreen();
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
+                    ..
+                }
+                | Event::Window {
+                    win_event: WindowEvent::Exposed,
+                    ..
+                } => {
+                    self.video_interface.refresh();
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.input_interface.add_controller(which);
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.input_interface.remove_controller(which);
                 }
                 _ => {}
             }
         }
-        return None;
+        None
+    }
+
+    // Fast-forward lasts as long as Tab is held down.
+    pub fn fast_forward(&self) -> bool {
+        self.event_pump
+            .keyboard_state()
+            .is_scancode_pressed(Scancode::Tab)
     }
 }
@@ -0,0 +1,294 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+use std::time::{Duration, Instant};
+
+// how long a message stays on screen, in frames
+const MESSAGE_FRAMES: u32 = 120;
+pub(super) const GLYPH_WIDTH: i32 = 3;
+pub(super) const GLYPH_HEIGHT: i32 = 5;
+// space around the text, in font pixels
+const MARGIN: i32 = 2;
+
+// 3x5 pixel font, one byte per row with the leftmost pixel in bit 2.
+// Lower case letters are drawn as upper case ones.
+const FONT: [(char, [u8; 5]); 52] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+];
+
+// The keys as they are laid out on the COSMAC VIP keypad.
+const KEYPAD_LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// Text and indicators drawn over the game image, in window pixels.
+// The OSD is drawn by the display after the CHIP-8 image and never
+// changes the emulated screen.
+pub struct Osd {
+    pub enabled: bool,
+    pub show_stats: bool,
+    pub show_keys: bool,
+    message: Option<(String, u32)>,
+    status: Option<String>,
+    stats: String,
+    keypad: [bool; 16],
+    // frames and instructions counted since `counting_since`
+    frames: u32,
+    cycles: u64,
+    counting_since: Instant,
+}
+
+impl Osd {
+    pub fn new(enabled: bool, show_stats: bool, show_keys: bool) -> Self {
+        Osd {
+            enabled,
+            show_stats,
+            show_keys,
+            message: None,
+            status: None,
+            stats: String::new(),
+            keypad: [false; 16],
+            frames: 0,
+            cycles: 0,
+            counting_since: Instant::now(),
+        }
+    }
+
+    // Shows a message for a couple of seconds.
+    pub fn message(&mut self, text: String) {
+        self.message = Some((text, MESSAGE_FRAMES));
+    }
+
+    // A lasting state, e.g. "Paused", shown until it is cleared.
+    // Returns whether it changed.
+    pub fn set_status(&mut self, status: Option<String>) -> bool {
+        if status == self.status {
+            return false;
+        }
+        self.status = status;
+        true
+    }
+
+    // Returns whether the keys shown changed.
+    pub fn set_keypad(&mut self, keypad: [bool; 16]) -> bool {
+        if keypad == self.keypad {
+            return false;
+        }
+        self.keypad = keypad;
+        self.show_keys
+    }
+
+    // Counts a displayed frame and the instructions run for it, and
+    // returns whether the OSD has to be drawn again.
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        let mut changed = false;
+
+        self.frames += 1;
+        self.cycles += cycles;
+        let elapsed = self.counting_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.stats = format!(
+                "{:.0} FPS {:.0} IPS",
+                self.frames as f64 / seconds,
+                self.cycles as f64 / seconds
+            );
+            self.frames = 0;
+            self.cycles = 0;
+            self.counting_since = Instant::now();
+            changed |= self.show_stats;
+        }
+
+        if let Some((_, frames)) = &mut self.message {
+            *frames -= 1;
+            if *frames == 0 {
+                self.message = None;
+                changed = true;
+            }
+        }
+
+        changed && self.enabled
+    }
+
+    pub fn render(&self, canvas: &mut Canvas<Window>) {
+        if !self.enabled {
+            return;
+        }
+        let (width, height) = match canvas.output_size() {
+            Ok((width, height)) => (width as i32, height as i32),
+            Err(_) => return,
+        };
+        // size of a font pixel
+        let size = (height / 120).max(1);
+        let line_height = (GLYPH_HEIGHT + MARGIN * 2) * size;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        if self.show_stats && !self.stats.is_empty() {
+            draw_text(canvas, &self.stats, 0, 0, size);
+        }
+        if let Some(status) = &self.status {
+            let x = width - text_width(status, size);
+            draw_text(canvas, status, x, 0, size);
+        }
+        if let Some((message, _)) = &self.message {
+            draw_text(canvas, message, 0, height - line_height, size);
+        }
+        if self.show_keys {
+            self.draw_keypad(canvas, width, height, size);
+        }
+        canvas.set_blend_mode(BlendMode::None);
+    }
+
+    // Draws the 16 keys in the bottom right corner, with the keys held
+    // down highlighted.
+    fn draw_keypad(&self, canvas: &mut Canvas<Window>, width: i32, height: i32, size: i32) {
+        let cell = (GLYPH_WIDTH + MARGIN * 2) * size;
+        let (left, top) = (width - cell * 4, height - cell * 4);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        let _ = canvas.fill_rect(Rect::new(left, top, (cell * 4) as u32, (cell * 4) as u32));
+
+        for (position, &key) in KEYPAD_LAYOUT.iter().enumerate() {
+            let x = left + (position as i32 % 4) * cell;
+            let y = top + (position as i32 / 4) * cell;
+            let color = if self.keypad[key] {
+                canvas.set_draw_color(Color::RGBA(255, 255, 255, 200));
+                let _ = canvas.fill_rect(Rect::new(x, y, cell as u32, cell as u32));
+                Color::RGB(0, 0, 0)
+            } else {
+                Color::RGB(128, 128, 128)
+            };
+            let digit = std::char::from_digit(key as u32, 16).unwrap_or('?');
+            let glyph_y = y + (cell - GLYPH_HEIGHT * size) / 2;
+            draw_glyph(canvas, digit, x + MARGIN * size, glyph_y, size, color);
+        }
+    }
+}
+
+fn text_width(text: &str, size: i32) -> i32 {
+    let characters = text.chars().count() as i32;
+    (characters * (GLYPH_WIDTH + 1) - 1 + MARGIN * 2) * size
+}
+
+// Draws white text on a dark box whose top left corner is at x, y.
+fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, size: i32) {
+    let box_height = (GLYPH_HEIGHT + MARGIN * 2) * size;
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    let _ = canvas.fill_rect(Rect::new(
+        x,
+        y,
+        text_width(text, size) as u32,
+        box_height as u32,
+    ));
+
+    let (text_x, text_y) = (x + MARGIN * size, y + MARGIN * size);
+    draw_string(
+        canvas,
+        text,
+        text_x,
+        text_y,
+        size,
+        Color::RGB(255, 255, 255),
+    );
+}
+
+// Draws the text with the built-in font, one column of space between
+// the characters. `size` is the size of a font pixel.
+pub(super) fn draw_string(
+    canvas: &mut Canvas<Window>,
+    text: &str,
+    x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+) {
+    let mut glyph_x = x;
+    for character in text.chars() {
+        draw_glyph(canvas, character, glyph_x, y, size, color);
+        glyph_x += (GLYPH_WIDTH + 1) * size;
+    }
+}
+
+fn draw_glyph(
+    canvas: &mut Canvas<Window>,
+    character: char,
+    x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+) {
+    let character = character.to_ascii_uppercase();
+    let rows = FONT
+        .iter()
+        .find(|&&(glyph, _)| glyph == character)
+        .or_else(|| FONT.iter().find(|&&(glyph, _)| glyph == '?'))
+        .map(|&(_, rows)| rows)
+        .unwrap_or_default();
+
+    let mut rects = Vec::new();
+    for (row, bits) in rows.iter().enumerate() {
+        for column in 0..GLYPH_WIDTH {
+            if bits & (0b100 >> column) != 0 {
+                rects.push(Rect::new(
+                    x + column * size,
+                    y + row as i32 * size,
+                    size as u32,
+                    size as u32,
+                ));
+            }
+        }
+    }
+    canvas.set_draw_color(color);
+    let _ = canvas.fill_rects(&rects);
+}
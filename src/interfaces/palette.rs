@@ -0,0 +1,76 @@
+use sdl2::pixels::Color;
+use std::str::FromStr;
+
+// Colours indexed by the value of a VRAM pixel: background, foreground,
+// and two more for interpreters drawing on two bit planes.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    fn new(name: &str, colors: [u32; 4]) -> Self {
+        Palette {
+            name: name.to_string(),
+            colors: [
+                rgb(colors[0]),
+                rgb(colors[1]),
+                rgb(colors[2]),
+                rgb(colors[3]),
+            ],
+        }
+    }
+
+    pub fn presets() -> Vec<Palette> {
+        vec![
+            Palette::new("green", [0x000000, 0x00E600, 0x007300, 0x00FF66]),
+            Palette::new("amber", [0x000000, 0xFFB000, 0x805800, 0xFFD480]),
+            Palette::new("white", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+            Palette::new("lcd", [0xF9FFB3, 0x3D8026, 0xABCC47, 0x00131A]),
+            // Octo presets
+            Palette::new("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+            Palette::new("hotdog", [0x000000, 0xFF0000, 0xFFFF00, 0xFFFFFF]),
+            Palette::new("gray", [0xAAAAAA, 0x000000, 0xFFFFFF, 0x666666]),
+            Palette::new("cga0", [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00]),
+            Palette::new("cga1", [0x000000, 0xFF00FF, 0x00FFFF, 0xFFFFFF]),
+        ]
+    }
+
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[pixel as usize % self.colors.len()]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::presets().remove(0)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Palette::presets()
+            .into_iter()
+            .find(|palette| palette.name.eq_ignore_ascii_case(s))
+            .ok_or(format!("unknown palette '{}'", s))
+    }
+}
+
+// Turns a 0xRRGGBB value into a colour.
+pub fn rgb(value: u32) -> Color {
+    Color::RGB((value >> 16) as u8, (value >> 8) as u8, value as u8)
+}
+
+// Parses colours written as "RRGGBB" or "#RRGGBB".
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("invalid colour '{}', expected RRGGBB", s));
+    }
+    u32::from_str_radix(hex, 16)
+        .map(rgb)
+        .map_err(|_| format!("invalid colour '{}', expected RRGGBB", s))
+}
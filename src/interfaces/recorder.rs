@@ -0,0 +1,371 @@
+use super::palette::{self, Palette};
+use super::screenshot::scale_pixels;
+use super::tone::{AudioSink, ToneOptions, WavWriter};
+use crate::hardware::color::{ColorZones, BACKGROUND_COLORS, FOREGROUND_COLORS};
+use crate::hardware::{CHIP8_HEIGHT, CHIP8_TIMER_RATE, CHIP8_WIDTH};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+enum Output {
+    // frames are only written once they change, so that their
+    // delay can cover all the frames they stay on screen
+    // frames in another palette than the starting one get their own
+    // colour table
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        global_colors: Vec<[u8; 3]>,
+        pending: Option<(Vec<u8>, Vec<[u8; 3]>)>,
+        pending_frames: u32,
+        written_frames: u32,
+    },
+    // YUV 4:4:4 stream, one frame per emulated frame
+    Y4m(BufWriter<File>),
+    // numbered PPM images, one per emulated frame
+    Ppm {
+        path: PathBuf,
+        frame: u32,
+    },
+}
+
+// Captures the emulated frames into an animated GIF, a Y4M video or a
+// sequence of PPM images, depending on the extension of the output file.
+// The sound can go to a WAV file next to it.
+pub struct Recorder {
+    output: Output,
+    audio: Option<WavWriter>,
+    // the current palette followed by the colours of CHIP-8X,
+    // see `color_indices`
+    colors: Vec<[u8; 3]>,
+    scale: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Recorder {
+    // The sound is recorded too when `audio` is given.
+    pub fn start(
+        path: &Path,
+        palette: &Palette,
+        scale: u32,
+        audio: Option<&ToneOptions>,
+    ) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        let colors = recording_colors(palette);
+        let output = match extension.as_deref() {
+            Some("gif") => {
+                let gif_width = u16::try_from(width).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "GIFs are at most {} pixels wide, too small for scale {}",
+                            u16::MAX,
+                            scale
+                        ),
+                    )
+                })?;
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder =
+                    gif::Encoder::new(file, gif_width, height as u16, &flatten(&colors))
+                        .map_err(gif_error)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(gif_error)?;
+                Output::Gif {
+                    encoder,
+                    global_colors: colors.clone(),
+                    pending: None,
+                    pending_frames: 0,
+                    written_frames: 0,
+                }
+            }
+            Some("y4m") => {
+                let mut file = BufWriter::new(File::create(path)?);
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width, height, CHIP8_TIMER_RATE as u32
+                )?;
+                Output::Y4m(file)
+            }
+            Some("ppm") => Output::Ppm {
+                path: path.to_path_buf(),
+                frame: 0,
+            },
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "recordings must be .gif, .y4m or .ppm files",
+                ))
+            }
+        };
+
+        let audio = match audio {
+            Some(options) => Some(WavWriter::create(&path.with_extension("wav"), options)?),
+            None => None,
+        };
+
+        Ok(Recorder {
+            output,
+            audio,
+            colors,
+            scale,
+            width,
+            height,
+        })
+    }
+
+    // Adds a frame to the recording, must be called once per emulated frame.
+    // The frame is in the current palette, or in the colours of CHIP-8X
+    // when they are given.
+    pub fn capture(
+        &mut self,
+        vram: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT],
+        palette: &Palette,
+        colors: Option<&ColorZones>,
+        beep: bool,
+    ) -> io::Result<()> {
+        if let Some(audio) = &mut self.audio {
+            audio.play_frame(beep)?;
+        }
+        self.colors = recording_colors(palette);
+
+        let pixels = match colors {
+            Some(colors) => color_indices(vram, colors, self.scale),
+            None => scale_pixels(vram, self.scale)
+                .into_iter()
+                .map(|pixel| pixel % PALETTE_SIZE as u8)
+                .collect(),
+        };
+        let (width, height) = (self.width, self.height);
+        let colors = &self.colors;
+        match &mut self.output {
+            Output::Gif {
+                encoder,
+                global_colors,
+                pending,
+                pending_frames,
+                written_frames,
+            } => {
+                let frame = (pixels, colors.clone());
+                if pending.as_ref() == Some(&frame) {
+                    *pending_frames += 1;
+                    return Ok(());
+                }
+                let frames = std::mem::replace(pending_frames, 1);
+                if let Some((previous, previous_colors)) = pending.replace(frame) {
+                    let local_colors = Some(&previous_colors).filter(|c| *c != global_colors);
+                    write_gif_frame(
+                        encoder,
+                        written_frames,
+                        &previous,
+                        local_colors,
+                        frames,
+                        width,
+                        height,
+                    )?;
+                }
+            }
+            Output::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                let yuv: Vec<(u8, u8, u8)> = pixels
+                    .iter()
+                    .map(|&pixel| {
+                        let [r, g, b] = colors[pixel as usize];
+                        rgb_to_yuv(r, g, b)
+                    })
+                    .collect();
+                let y: Vec<u8> = yuv.iter().map(|c| c.0).collect();
+                let u: Vec<u8> = yuv.iter().map(|c| c.1).collect();
+                let v: Vec<u8> = yuv.iter().map(|c| c.2).collect();
+                file.write_all(&y)?;
+                file.write_all(&u)?;
+                file.write_all(&v)?;
+            }
+            Output::Ppm { path, frame } => {
+                *frame += 1;
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let frame_path = path.with_file_name(format!("{}-{:06}.ppm", stem, frame));
+                let mut file = BufWriter::new(File::create(frame_path)?);
+                write!(file, "P6\n{} {}\n255\n", width, height)?;
+                for &pixel in &pixels {
+                    file.write_all(&colors[pixel as usize])?;
+                }
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes out whatever is still buffered.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(audio) = self.audio.take() {
+            audio.finish()?;
+        }
+
+        let (width, height) = (self.width, self.height);
+        match &mut self.output {
+            Output::Gif {
+                encoder,
+                global_colors,
+                pending,
+                pending_frames,
+                written_frames,
+            } => {
+                if let Some((pixels, colors)) = pending.take() {
+                    let local_colors = Some(&colors).filter(|c| *c != global_colors);
+                    let frames = *pending_frames;
+                    write_gif_frame(
+                        encoder,
+                        written_frames,
+                        &pixels,
+                        local_colors,
+                        frames,
+                        width,
+                        height,
+                    )?;
+                }
+                encoder.get_mut().flush()
+            }
+            Output::Y4m(file) => file.flush(),
+            Output::Ppm { .. } => Ok(()),
+        }
+    }
+}
+
+const PALETTE_SIZE: usize = 4;
+
+// All the colours a recording can use, in the order of the indices of
+// `color_indices`: the palette, then the foreground and the background
+// colours of CHIP-8X. 16 colours, so GIFs keep a single colour table.
+fn recording_colors(palette: &Palette) -> Vec<[u8; 3]> {
+    let mut colors: Vec<[u8; 3]> = palette
+        .colors
+        .iter()
+        .map(|color| [color.r, color.g, color.b])
+        .collect();
+    for &value in FOREGROUND_COLORS.iter().chain(BACKGROUND_COLORS.iter()) {
+        let color = palette::rgb(value);
+        colors.push([color.r, color.g, color.b]);
+    }
+    colors
+}
+
+// The scaled up image in the colours of CHIP-8X, as indices in
+// `recording_colors`, the same colours as in scaled screenshots.
+fn color_indices(
+    vram: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT],
+    colors: &ColorZones,
+    scale: usize,
+) -> Vec<u8> {
+    let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (column, row) = (x / scale, y / scale);
+            let index = if vram[row * CHIP8_WIDTH + column] > 0 {
+                PALETTE_SIZE + colors.foreground(column, row)
+            } else {
+                PALETTE_SIZE + FOREGROUND_COLORS.len() + colors.background()
+            };
+            pixels.push(index as u8);
+        }
+    }
+    pixels
+}
+
+fn flatten(colors: &[[u8; 3]]) -> Vec<u8> {
+    colors.iter().flatten().copied().collect()
+}
+
+// GIF delays are in hundredths of a second, so the 60Hz frames are
+// rounded against the total time recorded so far to avoid drifting.
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    written_frames: &mut u32,
+    pixels: &[u8],
+    local_colors: Option<&Vec<[u8; 3]>>,
+    frames: u32,
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    let centiseconds = |frames: u32| (frames as f64 * 100. / CHIP8_TIMER_RATE).round();
+    let delay = centiseconds(*written_frames + frames) - centiseconds(*written_frames);
+    *written_frames += frames;
+
+    let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+    frame.delay = delay as u16;
+    frame.palette = local_colors.map(|colors| flatten(colors));
+    encoder.write_frame(&frame).map_err(gif_error)
+}
+
+// BT.601 full range conversion.
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = 128. - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let v = 128. + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
+
+fn gif_error(err: gif::EncodingError) -> io::Error {
+    io::Error::other(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gif_frames_follow_the_palette() {
+        let path = std::env::temp_dir().join("chip8-recorder-palette-test.gif");
+        let green: Palette = "green".parse().unwrap();
+        let amber: Palette = "amber".parse().unwrap();
+        let mut vram = [0; CHIP8_WIDTH * CHIP8_HEIGHT];
+        vram[0] = 1;
+
+        let mut recorder = Recorder::start(&path, &green, 1, None).unwrap();
+        recorder.capture(&vram, &green, None, false).unwrap();
+        recorder.capture(&vram, &amber, None, false).unwrap();
+        recorder.capture(&vram, &amber, None, false).unwrap();
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::Decoder::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            decoder.global_palette(),
+            Some(&flatten(&recording_colors(&green))[..])
+        );
+        let delays_and_palettes: Vec<_> = std::iter::from_fn(|| {
+            let frame = decoder.read_next_frame().unwrap()?;
+            Some((frame.delay, frame.palette.clone()))
+        })
+        .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            delays_and_palettes,
+            vec![(2, None), (3, Some(flatten(&recording_colors(&amber)))),]
+        );
+    }
+
+    #[test]
+    fn gif_scale_must_fit_in_16_bits() {
+        let path = std::env::temp_dir().join("chip8-recorder-scale-test.gif");
+        let palette = Palette::default();
+
+        let err = Recorder::start(&path, &palette, 1024, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        let recorder = Recorder::start(&path, &palette, 1023, None).unwrap();
+        recorder.finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
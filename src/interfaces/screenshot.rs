@@ -0,0 +1,125 @@
+use super::palette::Palette;
+use crate::hardware::color::ColorZones;
+use crate::hardware::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug)]
+pub enum ScreenshotMode {
+    // one image pixel per CHIP-8 pixel, lit pixels are white
+    Raw,
+    // the CHIP-8 pixels scaled up and drawn with the palette colours,
+    // or with the colours of CHIP-8X
+    Scaled,
+}
+
+impl FromStr for ScreenshotMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ScreenshotMode::Raw),
+            "scaled" => Ok(ScreenshotMode::Scaled),
+            _ => Err(format!("unknown screenshot mode '{}'", s)),
+        }
+    }
+}
+
+pub fn save_png(
+    path: &Path,
+    vram: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT],
+    mode: ScreenshotMode,
+    palette: &Palette,
+    colors: Option<&ColorZones>,
+    scale: u32,
+) -> io::Result<()> {
+    let scale = match mode {
+        ScreenshotMode::Raw => 1,
+        ScreenshotMode::Scaled => scale.max(1) as usize,
+    };
+    let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_depth(png::BitDepth::Eight);
+    match (mode, colors) {
+        (ScreenshotMode::Raw, _) => encoder.set_color(png::ColorType::Grayscale),
+        (ScreenshotMode::Scaled, Some(colors)) => {
+            encoder.set_color(png::ColorType::RGB);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&color_pixels(vram, colors, scale))?;
+            return Ok(());
+        }
+        (ScreenshotMode::Scaled, None) => {
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_palette(
+                palette
+                    .colors
+                    .iter()
+                    .flat_map(|color| vec![color.r, color.g, color.b])
+                    .collect(),
+            );
+        }
+    }
+
+    let data: Vec<u8> = scale_pixels(vram, scale)
+        .into_iter()
+        .map(|pixel| match mode {
+            ScreenshotMode::Raw => (pixel > 0) as u8 * 255,
+            ScreenshotMode::Scaled => pixel % palette.colors.len() as u8,
+        })
+        .collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+// The scaled up image as RGB bytes, with each pixel in the colour of its
+// zone or the background.
+fn color_pixels(
+    vram: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT],
+    colors: &ColorZones,
+    scale: usize,
+) -> Vec<u8> {
+    let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let (column, row) = (x / scale, y / scale);
+            let color = if vram[row * CHIP8_WIDTH + column] > 0 {
+                colors.foreground_color(column, row)
+            } else {
+                colors.background_color()
+            };
+            data.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+        }
+    }
+    data
+}
+
+// Enlarges the VRAM image by repeating every pixel `scale` times
+// in each direction.
+pub fn scale_pixels(vram: &[u8; CHIP8_WIDTH * CHIP8_HEIGHT], scale: usize) -> Vec<u8> {
+    let (width, height) = (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(vram[(y / scale) * CHIP8_WIDTH + x / scale]);
+        }
+    }
+    pixels
+}
+
+// A file name in the working directory that doesn't clash with
+// earlier screenshots and recordings.
+pub fn default_path(extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    PathBuf::from(format!("chip8-{}.{}", timestamp, extension))
+}
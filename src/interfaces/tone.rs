@@ -0,0 +1,226 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::hardware::CHIP8_TIMER_RATE;
+
+pub const SAMPLE_RATE: i32 = 44100;
+// how long the tone takes to fade in and out, to avoid clicks
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+// Somewhere the buzzer can be played, live or into a file.
+pub trait AudioSink {
+    // Called once per emulated frame with the state of the buzzer.
+    fn play_frame(&mut self, beep: bool) -> io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ToneOptions {
+    // in Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // between 0 and 1
+    pub volume: f32,
+}
+
+// Parses the pitch of the buzzer, which must be below the Nyquist
+// frequency of the output to be heard as a tone.
+pub fn parse_frequency(s: &str) -> Result<f32, String> {
+    let nyquist = (SAMPLE_RATE / 2) as f32;
+    s.parse()
+        .ok()
+        .filter(|&frequency| frequency > 0. && frequency < nyquist)
+        .ok_or(format!(
+            "invalid tone frequency '{}', expected more than 0 and less than {} Hz",
+            s, nyquist
+        ))
+}
+
+impl Default for ToneOptions {
+    fn default() -> Self {
+        ToneOptions {
+            frequency: 240.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+// The buzzer tone, independent of any audio device. It keeps running
+// while the buzzer is off and is gated by a short envelope instead.
+pub struct Tone {
+    waveform: Waveform,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    // the envelope follows the gate by `envelope_step` per sample
+    pub gate: bool,
+    envelope: f32,
+    envelope_step: f32,
+    noise_register: u16,
+    noise: f32,
+}
+
+impl Tone {
+    pub fn new(sample_rate: i32, options: &ToneOptions) -> Self {
+        Tone {
+            waveform: options.waveform,
+            phase_inc: options.frequency / sample_rate as f32,
+            phase: 0.0,
+            volume: options.volume.clamp(0.0, 1.0),
+            gate: false,
+            envelope: 0.0,
+            envelope_step: 1.0 / (ENVELOPE_SECONDS * sample_rate as f32),
+            noise_register: 1,
+            noise: 1.0,
+        }
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            let target = if self.gate { 1.0 } else { 0.0 };
+            if self.envelope < target {
+                self.envelope = (self.envelope + self.envelope_step).min(target);
+            } else if self.envelope > target {
+                self.envelope = (self.envelope - self.envelope_step).max(target);
+            }
+
+            *x = self.volume * self.envelope * self.sample();
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        let value = match self.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise,
+        };
+
+        self.phase += self.phase_inc;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            // 15 bit LFSR, clocked once per period
+            let bit = (self.noise_register ^ (self.noise_register >> 1)) & 1;
+            self.noise_register = (self.noise_register >> 1) | (bit << 14);
+            self.noise = if self.noise_register & 1 == 1 {
+                1.0
+            } else {
+                -1.0
+            };
+        }
+        value
+    }
+}
+
+// Renders the buzzer into a 16 bit mono WAV file, with exactly as many
+// samples per frame as the emulated time requires.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    tone: Tone,
+    samples_written: u64,
+    frames: u64,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, options: &ToneOptions) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // the sizes are filled in by finish
+        write_header(&mut file, 0)?;
+        Ok(WavWriter {
+            file,
+            tone: Tone::new(SAMPLE_RATE, options),
+            samples_written: 0,
+            frames: 0,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        let data_size = (self.samples_written * 2) as u32;
+        self.file.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.file, data_size)?;
+        self.file.flush()
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn play_frame(&mut self, beep: bool) -> io::Result<()> {
+        self.frames += 1;
+        let total_samples = (self.frames as f64 * SAMPLE_RATE as f64 / CHIP8_TIMER_RATE) as u64;
+        let mut samples = vec![0.0; (total_samples - self.samples_written) as usize];
+        self.tone.gate = beep;
+        self.tone.fill(&mut samples);
+        for sample in samples {
+            let value = (sample * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.samples_written = total_samples;
+        Ok(())
+    }
+}
+
+fn write_header(file: &mut impl Write, data_size: u32) -> io::Result<()> {
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = SAMPLE_RATE as u32 * block_align as u32;
+
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVE")?;
+    file.write_all(b"fmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // PCM
+    file.write_all(&channels.to_le_bytes())?;
+    file.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+    file.write_all(&byte_rate.to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&bits_per_sample.to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_frequency() {
+        assert_eq!(parse_frequency("240"), Ok(240.));
+        assert_eq!(parse_frequency("0.5"), Ok(0.5));
+        assert_eq!(parse_frequency("22049.9"), Ok(22049.9));
+        for invalid in &["0", "-240", "22050", "1e9", "NaN", "inf", "loud"] {
+            assert!(parse_frequency(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::rom_db;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const ROM_EXTENSIONS: &[&str] = &["ch8", "c8"];
+// longest description shown for a ROM, in characters
+const DESCRIPTION_LENGTH: usize = 240;
+const RECENT_ROMS: usize = 5;
+
+// A ROM found in the ROM folder, with what is known about it.
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub description: String,
+}
+
+impl RomEntry {
+    pub fn new(path: &Path) -> Self {
+        let profile = rom_db::lookup(path);
+        let title = match profile {
+            Some(profile) => profile.title.to_string(),
+            None => path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let description = read_description(&path.with_extension("txt"))
+            .or_else(|| {
+                profile
+                    .and_then(|profile| profile.description)
+                    .map(String::from)
+            })
+            .unwrap_or_default();
+
+        RomEntry {
+            path: path.to_path_buf(),
+            title,
+            description,
+        }
+    }
+}
+
+// Lists the ROMs in a folder, sorted by file name.
+pub fn scan(folder: &Path) -> io::Result<Vec<RomEntry>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        if path.is_file() && ROM_EXTENSIONS.contains(&extension.as_deref().unwrap_or_default()) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths.iter().map(|path| RomEntry::new(path)).collect())
+}
+
+// The text next to a ROM, on a single line and without the lines
+// that are only decoration.
+fn read_description(path: &Path) -> Option<String> {
+    let content = fs::read(path).ok()?;
+    let content = String::from_utf8_lossy(&content);
+    let mut description = content
+        .lines()
+        .filter(|line| line.chars().any(char::is_alphanumeric))
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if description.len() > DESCRIPTION_LENGTH {
+        let end = (0..=DESCRIPTION_LENGTH)
+            .rev()
+            .find(|&index| description.is_char_boundary(index))
+            .unwrap_or(0);
+        description.truncate(end);
+        description.push_str("...");
+    }
+    Some(description).filter(|description| !description.is_empty())
+}
+
+// The ROMs played last, most recent first, kept in the home folder
+// between runs.
+pub struct RecentList {
+    path: Option<PathBuf>,
+    pub roms: Vec<PathBuf>,
+}
+
+impl RecentList {
+    pub fn load() -> Self {
+        let path = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| Path::new(&home).join(".chip8-emulator").join("recent"));
+        let roms = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .filter(|rom| rom.is_file())
+                    .take(RECENT_ROMS)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        RecentList { path, roms }
+    }
+
+    // Moves the ROM to the top of the list and saves it.
+    pub fn add(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|recent| recent != &rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(RECENT_ROMS);
+
+        if let Err(err) = self.save() {
+            eprintln!("Unable to save the recent ROMs:\n{}", err);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let content: String = self
+            .roms
+            .iter()
+            .map(|rom| format!("{}\n", rom.display()))
+            .collect();
+        fs::write(path, content)
+    }
+}
@@ -1,65 +1,851 @@
 mod hardware;
-use hardware::cpu::CPU;
+use hardware::font::{Font, FontSet};
+use hardware::memory::{self, MemoryLayout};
+use hardware::quirks::{Platform, QuirkOverrides, Quirks};
+use hardware::rom;
+use hardware::timing::Timing;
+use hardware::vip::VipRoms;
+use hardware::CHIP8_TIMER_RATE;
 
 mod interfaces;
-use sdl2::event::Event;
+use interfaces::display_interface::{DisplayOptions, Persistence, Scaling};
+use interfaces::effects::EffectChain;
+use interfaces::input_interface::{self, GamepadMapping};
+use interfaces::menu::Menu;
+use interfaces::palette::{self, Palette};
+use interfaces::recorder::Recorder;
+use interfaces::screenshot::{self, ScreenshotMode};
+use interfaces::tone::{self, AudioSink, ToneOptions, WavWriter, Waveform};
+use interfaces::Command;
+
+mod emulator;
+use emulator::Emulator;
+
+mod movie;
+use movie::InputMovie;
+
+mod library;
+use library::RecentList;
+
+mod rom_db;
+
+mod watcher;
+use watcher::RomWatcher;
 
 use clap::Clap;
+use sdl2::pixels::Color;
 use std::fs;
+use std::io;
 use std::process;
 
-use std::path::PathBuf;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// problems found in a ROM that are printed, the rest are only counted
+const MAX_ROM_WARNINGS: usize = 10;
+// frames emulated per displayed frame while fast-forwarding
+const FAST_FORWARD_FRAMES: u32 = 4;
 
 #[derive(Clap)]
 #[clap(version, author, about = "A simple Chip-8 emulator written in Rust.")]
 struct Opt {
     #[clap(short, long, default_value = "500")]
-    /// The emulation speed
+    /// The emulation speed, in instructions per second
     speed: f64,
+    #[clap(long, default_value = "uniform")]
+    /// How long instructions take: uniform (set by the speed) or vip (as on the COSMAC VIP)
+    timing: Timing,
+    #[clap(short, long, default_value = "chip8")]
+    /// The interpreter to emulate: chip8, vip, eti660, chip8e or chip8x
+    platform: Platform,
+    #[clap(long)]
+    /// Comma separated quirk settings, e.g. "key-release=off"
+    quirks: Option<QuirkOverrides>,
+    #[clap(long, parse(try_from_str = memory::parse_number))]
+    /// Address the ROM is loaded at and started from, e.g. 0x600
+    load_address: Option<usize>,
+    #[clap(long, parse(try_from_str = memory::parse_number))]
+    /// Address of the hexadecimal font in memory
+    font_address: Option<usize>,
+    #[clap(long, parse(try_from_str = memory::parse_number))]
+    /// Size of the memory, in bytes
+    ram_size: Option<usize>,
+    #[clap(long)]
+    /// Number of subroutine calls that can be nested
+    stack_depth: Option<usize>,
+    #[clap(long, parse(try_from_str = memory::parse_number))]
+    /// Address of the big SUPER-CHIP font in memory
+    big_font_address: Option<usize>,
+    #[clap(long)]
+    /// The digits of an interpreter: chip8, vip, dream6800, eti660, schip or octo
+    font: Option<FontSet>,
+    #[clap(long)]
+    /// Load the digits from a binary file, 80 bytes optionally followed by a big font
+    font_file: Option<PathBuf>,
+    #[clap(long)]
+    /// Emulate the whole COSMAC VIP, with the monitor ROM from the given file
+    vip_monitor: Option<PathBuf>,
+    #[clap(long)]
+    /// The CHIP-8 interpreter image the emulated VIP runs
+    vip_interpreter: Option<PathBuf>,
+    #[clap(long, default_value = "10")]
+    /// Initial size of a CHIP-8 pixel in the window
+    scale: u32,
+    #[clap(long, default_value = "integer")]
+    /// How the image is scaled to the window: integer or fit
+    scaling: Scaling,
+    #[clap(long)]
+    /// Start in fullscreen mode
+    fullscreen: bool,
+    #[clap(long, default_value = "green")]
+    /// The colour palette: green, amber, white, lcd, octo, hotdog, gray, cga0 or cga1
+    palette: Palette,
+    #[clap(long, parse(try_from_str = palette::parse_color))]
+    /// Custom foreground colour as RRGGBB
+    foreground: Option<Color>,
+    #[clap(long, parse(try_from_str = palette::parse_color))]
+    /// Custom background colour as RRGGBB
+    background: Option<Color>,
+    #[clap(long, conflicts_with = "blend")]
+    /// Let turned off pixels fade out over the given number of frames
+    phosphor: Option<u32>,
+    #[clap(long, conflicts_with = "phosphor")]
+    /// Show pixels lit in either of the last two frames, to reduce flickering
+    blend: bool,
+    #[clap(long, default_value = "none")]
+    /// Comma separated display effects: noise, scanlines, grid and bloom, e.g. "scanlines=0.5,bloom"
+    effects: EffectChain,
+    #[clap(long, default_value = "0")]
+    /// Seed of the random display effects
+    effects_seed: u64,
+    #[clap(long)]
+    /// Hide the on-screen display
+    no_osd: bool,
+    #[clap(long)]
+    /// Show the frames and instructions per second on screen
+    show_stats: bool,
+    #[clap(long)]
+    /// Show the keys held down on screen
+    show_keys: bool,
+    #[clap(long)]
+    /// Game controller to keypad mapping, e.g. "dpup=2,dpdown=8,a=5,leftx-=4,leftx+=6"
+    gamepad_map: Option<GamepadMapping>,
+    #[clap(long, parse(try_from_str = input_interface::parse_axis_threshold))]
+    /// How far an analog stick has to be pushed to press a key (1-32767)
+    axis_threshold: Option<u16>,
+    #[clap(long)]
+    /// Save a screenshot to the given PNG file when the emulator exits
+    screenshot_on_exit: Option<PathBuf>,
+    #[clap(long, default_value = "scaled")]
+    /// Screenshot format: raw (one pixel per CHIP-8 pixel) or scaled (with the palette colours)
+    screenshot_mode: ScreenshotMode,
+    #[clap(long)]
+    /// Record the game from the start to a .gif, .y4m or .ppm file
+    record: Option<PathBuf>,
+    #[clap(long)]
+    /// Also record the sound, to a WAV file next to the recording
+    record_audio: bool,
+    #[clap(long, default_value = "240", parse(try_from_str = tone::parse_frequency))]
+    /// Pitch of the buzzer, in Hz
+    tone_frequency: f32,
+    #[clap(long, default_value = "square")]
+    /// Waveform of the buzzer: square, sine, triangle or noise
+    waveform: Waveform,
+    #[clap(long, default_value = "0.25")]
+    /// Volume of the buzzer, between 0 and 1
+    volume: f32,
+    #[clap(long)]
+    /// Don't play any sound
+    mute: bool,
+    #[clap(long)]
+    /// Write the sound to a WAV file
+    audio_out: Option<PathBuf>,
+    #[clap(long)]
+    /// Replay the keypad input from a movie file
+    input_movie: Option<PathBuf>,
+    #[clap(long)]
+    /// Run without a window or sound, as fast as possible
+    headless: bool,
+    #[clap(long)]
+    /// In headless mode, stop after the given number of frames
+    frames: Option<u64>,
+    #[clap(long)]
+    /// In headless mode, save a screenshot after the given number of frames and stop
+    screenshot_at_frame: Option<u64>,
+    #[clap(long)]
+    /// Restart the game whenever the ROM file changes
+    watch: bool,
+    #[clap(long)]
+    /// Keep the speed set with + and - when the ROM is reloaded
+    watch_keep_speed: bool,
+    #[clap(long, default_value = "roms")]
+    /// Folder listed by the ROM menu
+    roms_dir: PathBuf,
     #[clap(takes_value = true)]
-    rom_file: PathBuf,
+    /// The game to play, the ROM menu is shown without it
+    rom_file: Option<PathBuf>,
 }
 
 fn main() {
     let opts = Opt::parse();
-    let emulation_speed: f64 = opts.speed;
-    let rom_content = fs::read(opts.rom_file).unwrap_or_else(|err| {
-        eprintln!("An error occurred while reading the ROM FILE:\n{}", err);
+    if let Err(err) = memory_layout(&opts).validate() {
+        eprintln!("Invalid memory layout: {}", err);
+        process::exit(1);
+    }
+    let firmware = load_firmware(&opts).unwrap_or_else(|err| {
+        eprintln!("An error occurred while reading the firmware:\n{}", err);
         process::exit(1);
     });
 
-    let sleep_duration = (1. / emulation_speed * 1000.) as u128;
+    // a ROM given on the command line has to load, without it the
+    // emulator starts with the menu
+    let mut next_game = opts.rom_file.as_ref().map(|rom_file| {
+        let emulator = load_emulator(rom_file, &opts, &firmware).unwrap_or_else(|err| {
+            eprintln!("An error occurred while reading the ROM FILE:\n{}", err);
+            process::exit(1);
+        });
+        (rom_file.clone(), emulator)
+    });
+
+    let movie = opts.input_movie.as_ref().map(|path| {
+        InputMovie::load(path).unwrap_or_else(|err| {
+            eprintln!("An error occurred while reading the input movie:\n{}", err);
+            process::exit(1);
+        })
+    });
+
+    let mut palette = opts.palette.clone();
+    if opts.foreground.is_some() || opts.background.is_some() {
+        palette.name = String::from("custom");
+        palette.colors[1] = opts.foreground.unwrap_or(palette.colors[1]);
+        palette.colors[0] = opts.background.unwrap_or(palette.colors[0]);
+    }
+
+    let persistence = match (opts.phosphor, opts.blend) {
+        (Some(frames), _) => Persistence::Phosphor(frames),
+        (None, true) => Persistence::Blend,
+        (None, false) => Persistence::Off,
+    };
 
-    let mut cpu = CPU::new();
+    let tone_options = ToneOptions {
+        frequency: opts.tone_frequency,
+        waveform: opts.waveform,
+        volume: opts.volume,
+    };
+
+    if opts.headless {
+        let mut emulator = match next_game {
+            Some((_, emulator)) => emulator,
+            None => {
+                eprintln!("Headless mode needs a ROM FILE");
+                process::exit(1);
+            }
+        };
+        run_headless(
+            &mut emulator,
+            &opts,
+            &palette,
+            &tone_options,
+            movie.as_ref(),
+        );
+        return;
+    }
 
-    cpu.load_memory(&rom_content);
+    let display_options = DisplayOptions {
+        scale: opts.scale,
+        scaling: opts.scaling,
+        fullscreen: opts.fullscreen,
+        palette,
+        persistence,
+        effects: opts.effects.clone(),
+        seed: opts.effects_seed,
+        osd: !opts.no_osd,
+        show_stats: opts.show_stats,
+        show_keys: opts.show_keys,
+    };
 
-    let mut interface_manager = interfaces::InterfaceManager::new();
+    let mut interface_manager = interfaces::InterfaceManager::new(
+        display_options,
+        gamepad_mapping(&opts, opts.rom_file.as_deref()),
+        &tone_options,
+        opts.mute,
+    );
+    let mut recent = RecentList::load();
+    let mut capture = Capture::start(
+        &opts,
+        interface_manager.video_interface.palette(),
+        &tone_options,
+    );
 
     loop {
-        let current_time = Instant::now();
+        let (rom_file, emulator) = match next_game.take() {
+            Some(game) => game,
+            None => {
+                let rom_file = match run_menu(&mut interface_manager, &opts, &recent) {
+                    Some(rom_file) => rom_file,
+                    None => break,
+                };
+                match load_emulator(&rom_file, &opts, &firmware) {
+                    Ok(emulator) => (rom_file, emulator),
+                    Err(err) => {
+                        eprintln!("Unable to load {}:\n{}", rom_file.display(), err);
+                        interface_manager
+                            .video_interface
+                            .show_message(String::from("Unable to load the ROM"));
+                        continue;
+                    }
+                }
+            }
+        };
 
-        if let Some(Event::Quit { timestamp: _ }) = interface_manager.run() {
-            break;
+        recent.add(&rom_file);
+        interface_manager
+            .input_interface
+            .set_mapping(gamepad_mapping(&opts, Some(&rom_file)));
+
+        match run_game(
+            &rom_file,
+            emulator,
+            movie.as_ref(),
+            &mut interface_manager,
+            &opts,
+            &tone_options,
+            &mut capture,
+        ) {
+            GameExit::Quit => break,
+            GameExit::Menu => {}
+            GameExit::Open(rom_file) => match load_emulator(&rom_file, &opts, &firmware) {
+                Ok(emulator) => next_game = Some((rom_file, emulator)),
+                Err(err) => eprintln!("Unable to load {}:\n{}", rom_file.display(), err),
+            },
+        }
+    }
+    capture.finish();
+}
+
+// What the emulated machine needs besides the game.
+struct Firmware {
+    font: Font,
+    vip: Option<VipRoms>,
+}
+
+fn load_emulator(rom_file: &Path, opts: &Opt, firmware: &Firmware) -> io::Result<Emulator> {
+    let rom_content = read_rom(rom_file, opts)?;
+
+    let mut emulator = Emulator::new(
+        quirks(opts),
+        memory_layout(opts),
+        firmware.font.clone(),
+        rom_content,
+        opts.speed,
+    );
+    emulator.set_timing(opts.timing);
+    emulator.set_extension(opts.platform.extension());
+    if let Some(roms) = &firmware.vip {
+        emulator.use_vip(roms);
+    }
+    Ok(emulator)
+}
+
+// The quirks of the platform, with the changes given on the command line.
+fn quirks(opts: &Opt) -> Quirks {
+    let mut quirks = opts.platform.quirks();
+    if let Some(overrides) = &opts.quirks {
+        overrides.apply(&mut quirks);
+    }
+    quirks
+}
+
+// The layout of the platform, with the changes given on the command line.
+fn memory_layout(opts: &Opt) -> MemoryLayout {
+    let mut layout = opts.platform.memory_layout();
+    layout.start = opts.load_address.unwrap_or(layout.start);
+    layout.font_base = opts.font_address.unwrap_or(layout.font_base);
+    layout.ram_size = opts.ram_size.unwrap_or(layout.ram_size);
+    layout.stack_depth = opts.stack_depth.unwrap_or(layout.stack_depth);
+    layout.big_font_base = opts.big_font_address.unwrap_or(layout.big_font_base);
+    layout
+}
+
+// The font file given on the command line, or the built-in font chosen
+// or the one of the platform, and the ROMs of the VIP to emulate it.
+fn load_firmware(opts: &Opt) -> io::Result<Firmware> {
+    let font = match &opts.font_file {
+        Some(path) => Font::load(path)?,
+        None => opts.font.unwrap_or_else(|| opts.platform.font_set()).font(),
+    };
+    let vip = match (&opts.vip_monitor, &opts.vip_interpreter) {
+        (Some(monitor), Some(interpreter)) => Some(VipRoms::load(monitor, interpreter)?),
+        (None, None) => None,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the VIP needs both --vip-monitor and --vip-interpreter",
+            ))
+        }
+    };
+    Ok(Firmware { font, vip })
+}
+
+fn read_rom(rom_file: &Path, opts: &Opt) -> io::Result<Vec<u8>> {
+    let rom = fs::read(rom_file)?;
+    check_rom(rom_file, &rom, opts)?;
+    Ok(rom)
+}
+
+// Prints a summary of the ROM and what may keep it from running
+// properly, fails if it can't run at all.
+fn check_rom(rom_file: &Path, rom: &[u8], opts: &Opt) -> io::Result<()> {
+    let info = rom::check(rom, opts.platform, &quirks(opts), &memory_layout(opts))?;
+    println!("{}: {}", rom_file.display(), info);
+    for warning in info.warnings.iter().take(MAX_ROM_WARNINGS) {
+        eprintln!("Warning: {}", warning);
+    }
+    if info.warnings.len() > MAX_ROM_WARNINGS {
+        eprintln!(
+            "Warning: {} more problems",
+            info.warnings.len() - MAX_ROM_WARNINGS
+        );
+    }
+    Ok(())
+}
+
+// The mapping given on the command line, or the one of the ROM database.
+fn gamepad_mapping(opts: &Opt, rom_file: Option<&Path>) -> GamepadMapping {
+    let mut gamepad_mapping = opts.gamepad_map.clone().unwrap_or_else(|| {
+        rom_file
+            .and_then(rom_db::lookup)
+            .and_then(|profile| profile.gamepad_mapping)
+            .map(|mapping| mapping.parse().unwrap())
+            .unwrap_or_default()
+    });
+    if let Some(threshold) = opts.axis_threshold {
+        gamepad_mapping.axis_threshold = threshold;
+    }
+    gamepad_mapping
+}
+
+// Lets the user pick a ROM, returns None to quit.
+fn run_menu(
+    interface_manager: &mut interfaces::InterfaceManager,
+    opts: &Opt,
+    recent: &RecentList,
+) -> Option<PathBuf> {
+    let frame_duration = Duration::from_secs_f64(1. / CHIP8_TIMER_RATE);
+    let mut menu = Menu::new(&opts.roms_dir, &recent.roms);
+    interface_manager.video_interface.set_status(None);
+    let _ = interface_manager.audio_interface.play_frame(false);
+
+    loop {
+        let frame_start = Instant::now();
+
+        match interface_manager.run() {
+            Some(Command::Quit) | Some(Command::Back) => return None,
+            Some(Command::Up) => menu.up(1),
+            Some(Command::Down) => menu.down(1),
+            Some(Command::PageUp) => menu.page_up(),
+            Some(Command::PageDown) => menu.page_down(),
+            Some(Command::Select) => {
+                if let Some(rom) = menu.selected() {
+                    return Some(rom.path.clone());
+                }
+            }
+            Some(Command::Open(rom_file)) => return Some(rom_file),
+            _ => {}
+        }
+
+        interface_manager.video_interface.update_osd(0, [false; 16]);
+        interface_manager.video_interface.show_menu(&mut menu);
+
+        if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+enum GameExit {
+    Quit,
+    Menu,
+    // another ROM was dropped on the window
+    Open(PathBuf),
+}
+
+// Plays the game until the user quits, goes back to the menu or drops
+// another ROM on the window.
+fn run_game(
+    rom_file: &Path,
+    mut emulator: Emulator,
+    movie: Option<&InputMovie>,
+    interface_manager: &mut interfaces::InterfaceManager,
+    opts: &Opt,
+    tone_options: &ToneOptions,
+    capture: &mut Capture,
+) -> GameExit {
+    interface_manager
+        .video_interface
+        .draw(emulator.vram(), emulator.color_zones());
+
+    let frame_duration = Duration::from_secs_f64(1. / CHIP8_TIMER_RATE);
+    let initial_cycles = emulator.cycles_per_frame();
+    let mut paused = false;
+    let mut watcher = if opts.watch {
+        Some(RomWatcher::new(rom_file))
+    } else {
+        None
+    };
+
+    let exit = loop {
+        let frame_start = Instant::now();
+        let mut advance = false;
+
+        let command = interface_manager.run();
+        match command {
+            Some(Command::Quit) => break GameExit::Quit,
+            Some(Command::Back) => break GameExit::Menu,
+            Some(Command::Open(rom_file)) => break GameExit::Open(rom_file),
+            Some(Command::Screenshot) => {
+                let palette = interface_manager.video_interface.palette();
+                let path = screenshot::default_path("png");
+                let message = if take_screenshot(&path, &emulator, opts, palette) {
+                    "Screenshot saved"
+                } else {
+                    "Screenshot failed"
+                };
+                interface_manager
+                    .video_interface
+                    .show_message(String::from(message));
+            }
+            Some(Command::ToggleRecording) => {
+                let message = match capture.recorder.take() {
+                    Some(recording) => {
+                        stop_recording(recording);
+                        "Recording stopped"
+                    }
+                    None => {
+                        let palette = interface_manager.video_interface.palette();
+                        capture.recorder = start_recording(
+                            &screenshot::default_path("gif"),
+                            palette,
+                            opts,
+                            tone_options,
+                        );
+                        if capture.recorder.is_some() {
+                            "Recording started"
+                        } else {
+                            "Recording failed"
+                        }
+                    }
+                };
+                interface_manager
+                    .video_interface
+                    .show_message(String::from(message));
+            }
+            Some(Command::TogglePause) => paused = !paused,
+            Some(Command::FrameAdvance) => {
+                advance = paused;
+                paused = true;
+            }
+            Some(Command::SoftReset) => {
+                emulator.reset();
+                interface_manager
+                    .video_interface
+                    .draw(emulator.vram(), emulator.color_zones());
+                interface_manager
+                    .video_interface
+                    .show_message(String::from("Reset"));
+            }
+            Some(Command::HardReset) => match read_rom(rom_file, opts) {
+                Ok(rom) => {
+                    emulator.load_rom(rom);
+                    emulator.set_cycles_per_frame(initial_cycles);
+                    interface_manager
+                        .video_interface
+                        .draw(emulator.vram(), emulator.color_zones());
+                    interface_manager
+                        .video_interface
+                        .show_message(String::from("ROM reloaded"));
+                }
+                Err(err) => eprintln!("Unable to reload the ROM FILE:\n{}", err),
+            },
+            Some(Command::SpeedUp) | Some(Command::SpeedDown) => {
+                let step = if matches!(command, Some(Command::SpeedUp)) {
+                    1.
+                } else {
+                    -1.
+                };
+                let message = if emulator.has_fixed_speed() {
+                    String::from("Speed is fixed in this mode")
+                } else {
+                    emulator.set_cycles_per_frame(emulator.cycles_per_frame() + step);
+                    format!("Speed: {} ipf", emulator.cycles_per_frame())
+                };
+                interface_manager.video_interface.show_message(message);
+            }
+            _ => {}
         }
 
-        let keypad = interface_manager
+        if let Some(rom) = watcher.as_mut().and_then(RomWatcher::poll) {
+            match check_rom(rom_file, &rom, opts) {
+                Ok(()) => {
+                    emulator.load_rom(rom);
+                    if !opts.watch_keep_speed {
+                        emulator.set_cycles_per_frame(initial_cycles);
+                    }
+                    interface_manager
+                        .video_interface
+                        .draw(emulator.vram(), emulator.color_zones());
+                    interface_manager
+                        .video_interface
+                        .show_message(String::from("ROM changed, reloaded"));
+                }
+                Err(err) => {
+                    eprintln!("Unable to reload the ROM FILE:\n{}", err);
+                    interface_manager
+                        .video_interface
+                        .show_message(String::from("ROM changed, unable to load it"));
+                }
+            }
+        }
+
+        let fast_forward = !paused && interface_manager.fast_forward();
+        let frames = if paused {
+            advance as u32
+        } else if fast_forward {
+            FAST_FORWARD_FRAMES
+        } else {
+            1
+        };
+
+        let held = interface_manager
             .input_interface
             .poll(&interface_manager.event_pump);
+        let mut updated_vram = false;
+        let mut beep = false;
+        let mut cycles = 0;
+        let mut waiting_for_key = false;
+        let mut keypad = held;
+        for _ in 0..frames {
+            keypad = held;
+            if let Some(movie) = movie {
+                let replayed = movie.keypad(emulator.frame_count);
+                for (key, pressed) in keypad.iter_mut().zip(replayed.iter()) {
+                    *key |= pressed;
+                }
+            }
 
-        let state = cpu.emulate_cycle(keypad);
-
-        if state.updated_vram {
-            interface_manager.video_interface.draw(&cpu.vram);
+            let state = emulator.run_frame(keypad);
+            capture.frame(
+                &emulator,
+                interface_manager.video_interface.palette(),
+                state.beep,
+            );
+            updated_vram |= state.updated_vram;
+            beep = state.beep;
+            cycles += state.cycles as u64;
+            waiting_for_key = state.waiting_for_key;
         }
+        interface_manager.video_interface.update_osd(cycles, keypad);
 
-        if state.beep {
-            interface_manager.audio_interface.beep();
+        let animated = frames > 0 && interface_manager.video_interface.is_animated();
+        if updated_vram || animated {
+            interface_manager
+                .video_interface
+                .draw(emulator.vram(), emulator.color_zones());
+        }
+        let status = if paused {
+            Some(String::from("Paused"))
+        } else if fast_forward {
+            Some(String::from("Fast forward"))
+        } else if waiting_for_key {
+            Some(String::from("Waiting for a key"))
+        } else if emulator.cycles_per_frame() != initial_cycles {
+            Some(format!("Speed: {} ipf", emulator.cycles_per_frame()))
         } else {
-            interface_manager.audio_interface.no_beep();
+            None
+        };
+        interface_manager.video_interface.set_status(status);
+        interface_manager.video_interface.present();
+
+        let _ = interface_manager.audio_interface.play_frame(beep);
+
+        if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
         }
+    };
+
+    if let GameExit::Quit = exit {
+        if let Some(path) = &opts.screenshot_on_exit {
+            let palette = interface_manager.video_interface.palette();
+            take_screenshot(path, &emulator, opts, palette);
+        }
+    }
+    exit
+}
+
+// Runs the emulator without any interface, with the keys of the input
+// movie or no key pressed, until the requested number of frames has
+// been emulated.
+fn run_headless(
+    emulator: &mut Emulator,
+    opts: &Opt,
+    palette: &Palette,
+    tone_options: &ToneOptions,
+    movie: Option<&InputMovie>,
+) {
+    if opts.frames.is_none() && opts.screenshot_at_frame.is_none() {
+        eprintln!("Headless mode needs --frames or --screenshot-at-frame to know when to stop");
+        process::exit(1);
+    }
+
+    let mut capture = Capture::start(opts, palette, tone_options);
+
+    loop {
+        if Some(emulator.frame_count) == opts.screenshot_at_frame {
+            let path = opts
+                .screenshot_on_exit
+                .clone()
+                .unwrap_or_else(|| screenshot::default_path("png"));
+            take_screenshot(&path, emulator, opts, palette);
+            break;
+        }
+        if Some(emulator.frame_count) == opts.frames {
+            if let Some(path) = &opts.screenshot_on_exit {
+                take_screenshot(path, emulator, opts, palette);
+            }
+            break;
+        }
+
+        let keypad = movie
+            .map(|movie| movie.keypad(emulator.frame_count))
+            .unwrap_or([false; 16]);
+        let state = emulator.run_frame(keypad);
+        capture.frame(emulator, palette, state.beep);
+    }
+
+    capture.finish();
+}
+
+// The recording and the sound file of the session. They are only opened
+// once, so that the games started one after the other from the menu follow
+// each other in the same files instead of overwriting them.
+struct Capture {
+    recorder: Option<Recorder>,
+    audio_out: Option<WavWriter>,
+}
+
+impl Capture {
+    fn start(opts: &Opt, palette: &Palette, tone_options: &ToneOptions) -> Self {
+        Capture {
+            recorder: opts
+                .record
+                .as_ref()
+                .and_then(|path| start_recording(path, palette, opts, tone_options)),
+            audio_out: opts
+                .audio_out
+                .as_ref()
+                .and_then(|path| open_wav(path, tone_options)),
+        }
+    }
 
-        while current_time.elapsed().as_millis() < sleep_duration {}
+    fn frame(&mut self, emulator: &Emulator, palette: &Palette, beep: bool) {
+        record_frame(&mut self.recorder, emulator, palette, beep);
+        write_audio(&mut self.audio_out, beep);
+    }
+
+    fn finish(self) {
+        if let Some(recording) = self.recorder {
+            stop_recording(recording);
+        }
+        if let Some(wav) = self.audio_out {
+            close_wav(wav);
+        }
+    }
+}
+
+fn start_recording(
+    path: &Path,
+    palette: &Palette,
+    opts: &Opt,
+    tone_options: &ToneOptions,
+) -> Option<Recorder> {
+    let audio = if opts.record_audio {
+        Some(tone_options)
+    } else {
+        None
+    };
+    match Recorder::start(path, palette, opts.scale, audio) {
+        Ok(recorder) => {
+            println!("Recording to {}", path.display());
+            Some(recorder)
+        }
+        Err(err) => {
+            eprintln!("Unable to start recording:\n{}", err);
+            None
+        }
+    }
+}
+
+fn record_frame(
+    recorder: &mut Option<Recorder>,
+    emulator: &Emulator,
+    palette: &Palette,
+    beep: bool,
+) {
+    if let Some(recording) = recorder {
+        if let Err(err) = recording.capture(emulator.vram(), palette, emulator.color_zones(), beep)
+        {
+            eprintln!("Recording stopped:\n{}", err);
+            *recorder = None;
+        }
+    }
+}
+
+fn stop_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok(()) => println!("Recording saved"),
+        Err(err) => eprintln!("Unable to save the recording:\n{}", err),
+    }
+}
+
+fn open_wav(path: &Path, tone_options: &ToneOptions) -> Option<WavWriter> {
+    WavWriter::create(path, tone_options)
+        .map_err(|err| eprintln!("Unable to write the sound to {}:\n{}", path.display(), err))
+        .ok()
+}
+
+fn write_audio(wav: &mut Option<WavWriter>, beep: bool) {
+    if let Some(writer) = wav {
+        if let Err(err) = writer.play_frame(beep) {
+            eprintln!("Sound output stopped:\n{}", err);
+            *wav = None;
+        }
+    }
+}
+
+fn close_wav(wav: WavWriter) {
+    if let Err(err) = wav.finish() {
+        eprintln!("Unable to save the sound:\n{}", err);
+    }
+}
+
+// Returns whether the screenshot could be saved.
+fn take_screenshot(path: &Path, emulator: &Emulator, opts: &Opt, palette: &Palette) -> bool {
+    match screenshot::save_png(
+        path,
+        emulator.vram(),
+        opts.screenshot_mode,
+        palette,
+        emulator.color_zones(),
+        opts.scale,
+    ) {
+        Ok(()) => {
+            println!("Screenshot saved to {}", path.display());
+            true
+        }
+        Err(err) => {
+            eprintln!("Unable to save the screenshot:\n{}", err);
+            false
+        }
     }
 }
@@ -0,0 +1,69 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Keypad input recorded per frame, to replay a game without a player.
+//
+// A movie is a text file with one line per change of the keypad state:
+// the frame number followed by the hex digits of the keys held from
+// that frame on, or `-` for no key. Lines starting with `#` are ignored.
+//
+//     # frame keys
+//     0   -
+//     30  5
+//     45  46
+pub struct InputMovie {
+    // frame number and keypad state, sorted by frame
+    changes: Vec<(u64, [bool; 16])>,
+}
+
+impl InputMovie {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", line + 1, message),
+            )
+        };
+
+        let mut changes = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let frame = fields
+                .next()
+                .and_then(|frame| frame.parse::<u64>().ok())
+                .ok_or_else(|| invalid(number, "expected a frame number"))?;
+            let mut keypad = [false; 16];
+            match fields.next() {
+                Some("-") => {}
+                Some(keys) => {
+                    for key in keys.chars() {
+                        let key = key
+                            .to_digit(16)
+                            .ok_or_else(|| invalid(number, "keys must be hex digits"))?;
+                        keypad[key as usize] = true;
+                    }
+                }
+                None => return Err(invalid(number, "expected the keys held")),
+            }
+            changes.push((frame, keypad));
+        }
+        changes.sort_by_key(|&(frame, _)| frame);
+
+        Ok(InputMovie { changes })
+    }
+
+    pub fn keypad(&self, frame: u64) -> [bool; 16] {
+        self.changes
+            .iter()
+            .take_while(|&&(start, _)| start <= frame)
+            .last()
+            .map(|&(_, keypad)| keypad)
+            .unwrap_or([false; 16])
+    }
+}
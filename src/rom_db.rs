@@ -0,0 +1,73 @@
+use std::path::Path;
+
+// Per-ROM defaults for the games shipped in the roms folder.
+// ROMs are matched by title, i.e. the file name up to the
+// "[author, year]" or "(...)" part.
+pub struct RomProfile {
+    pub title: &'static str,
+    // shown in the ROM menu when there is no text file next to the ROM
+    pub description: Option<&'static str>,
+    pub gamepad_mapping: Option<&'static str>,
+}
+
+const ROM_PROFILES: &[RomProfile] = &[
+    RomProfile {
+        title: "Tetris",
+        description: Some("Q rotates the piece, W and E move it left and right, A drops it."),
+        gamepad_mapping: Some("dpleft=5,dpright=6,dpdown=7,leftx-=5,leftx+=6,lefty+=7,a=4,b=4"),
+    },
+    RomProfile {
+        title: "Space Invaders",
+        description: Some("Q and E move left and right, W shoots."),
+        gamepad_mapping: Some("dpleft=4,dpright=6,leftx-=4,leftx+=6,a=5,b=5"),
+    },
+    RomProfile {
+        title: "Brix",
+        description: Some("Q and E move the paddle left and right."),
+        gamepad_mapping: Some("dpleft=4,dpright=6,leftx-=4,leftx+=6"),
+    },
+    RomProfile {
+        title: "Breakout",
+        description: Some("Q and E move the paddle left and right."),
+        gamepad_mapping: Some("dpleft=4,dpright=6,leftx-=4,leftx+=6"),
+    },
+    RomProfile {
+        title: "Pong",
+        description: Some("1 and Q move the left paddle, 4 and R the right one."),
+        gamepad_mapping: Some("dpup=1,dpdown=4,lefty-=1,lefty+=4"),
+    },
+];
+
+pub fn lookup(rom_file: &Path) -> Option<&'static RomProfile> {
+    let stem = rom_file.file_stem()?.to_str()?;
+    let title = stem.split(['[', '(']).next()?.trim();
+
+    ROM_PROFILES
+        .iter()
+        .find(|profile| profile.title.eq_ignore_ascii_case(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::input_interface::GamepadMapping;
+
+    #[test]
+    fn gamepad_mappings_parse() {
+        for profile in ROM_PROFILES {
+            if let Some(mapping) = profile.gamepad_mapping {
+                if let Err(err) = mapping.parse::<GamepadMapping>() {
+                    panic!("{}: {}", profile.title, err);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn looks_up_by_title() {
+        let profile = lookup(Path::new("roms/Tetris [Fran Dachille, 1991].ch8")).unwrap();
+
+        assert_eq!(profile.title, "Tetris");
+        assert!(lookup(Path::new("roms/Unknown Game.ch8")).is_none());
+    }
+}
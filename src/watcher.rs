@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// how often the file is looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// how long the file has to stay the same before it is reloaded, so that
+// a ROM still being written by an assembler isn't loaded half way
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+// Notices when a ROM file changes on disk, by polling its size and
+// modification time.
+pub struct RomWatcher {
+    path: PathBuf,
+    // size and modification time of the version running
+    loaded: Option<(u64, SystemTime)>,
+    // a newer version and when it was first seen like this
+    changed: Option<((u64, SystemTime), Instant)>,
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path) -> Self {
+        RomWatcher {
+            path: path.to_path_buf(),
+            loaded: stamp(path),
+            changed: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Returns the new content of the ROM once it has changed and settled.
+    // Must be called regularly, e.g. once per frame.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let current = stamp(&self.path);
+        if current.is_none() || current == self.loaded {
+            // deleted while being rewritten, or unchanged
+            self.changed = None;
+            return None;
+        }
+        let current = current?;
+        match self.changed {
+            Some((seen, since)) if seen == current => {
+                if since.elapsed() < SETTLE_TIME {
+                    return None;
+                }
+            }
+            _ => {
+                self.changed = Some((current, Instant::now()));
+                return None;
+            }
+        }
+
+        let content = fs::read(&self.path).ok()?;
+        // still being written, or truncated before being written again
+        if content.is_empty() || stamp(&self.path) != Some(current) {
+            self.changed = None;
+            return None;
+        }
+        self.loaded = Some(current);
+        self.changed = None;
+        Some(content)
+    }
+}
+
+fn stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}